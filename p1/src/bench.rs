@@ -0,0 +1,108 @@
+use std::iter;
+use std::time::{Duration, Instant};
+
+use crate::search::{self, Method, SearchOptions, DEFAULT_CHUNK_SIZE};
+
+/// What to sweep over in a benchmark.
+#[derive(Clone, Debug)]
+pub struct BenchOptions {
+    /// Methods to time, in order.
+    pub methods: Vec<Method>,
+    /// Thread counts to time each method with. One thread is always timed
+    /// first as the baseline for the speedup.
+    pub thread_counts: Vec<u64>,
+    pub chunk_size: u64,
+    /// Number of times each combination is run, keeping the fastest.
+    pub runs: u64,
+}
+
+impl BenchOptions {
+    /// Times the sieve and Miller-Rabin with every power of two threads up to
+    /// `threads`, and `threads` itself.
+    pub fn new(threads: u64) -> BenchOptions {
+        let mut thread_counts: Vec<u64> = (0..64)
+            .map(|i| 1 << i)
+            .take_while(|x| *x < threads)
+            .collect();
+        thread_counts.push(threads);
+        BenchOptions {
+            methods: vec![Method::Sieve, Method::MillerRabin],
+            thread_counts,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            runs: 1,
+        }
+    }
+}
+
+/// Timing of one search.
+#[derive(Clone, Copy, Debug)]
+pub struct Measurement {
+    pub method: Method,
+    pub threads: u64,
+    /// Fastest wall time of the runs.
+    pub elapsed: Duration,
+    /// Number of primes found, which should be the same for every search.
+    pub primes: u64,
+    /// Numbers searched per second.
+    pub throughput: f64,
+    /// How many times faster than the same method with one thread.
+    pub speedup: f64,
+}
+
+impl Measurement {
+    /// Speedup per thread, 1 for perfect scaling.
+    pub fn efficiency(&self) -> f64 {
+        self.speedup / self.threads as f64
+    }
+}
+
+/// Searches `[start, end]` `runs` times, returning the fastest time and the
+/// number of primes found.
+fn time_search(start: u64, end: u64, options: &SearchOptions, runs: u64) -> (Duration, u64) {
+    let mut best = (Duration::MAX, 0);
+    for _ in 0..runs.max(1) {
+        let started = Instant::now();
+        let mut primes = 0;
+        search::find_primes_in_range(start, end, options, |_| primes += 1);
+        best = best.min((started.elapsed(), primes));
+    }
+    best
+}
+
+/// Times searches of `[start, end]` with every combination of method and
+/// thread count in `options`, calling `f` with each measurement as soon as it
+/// has been taken.
+pub fn sweep<F>(start: u64, end: u64, options: &BenchOptions, mut f: F)
+where
+    F: FnMut(&Measurement),
+{
+    if start > end {
+        return;
+    }
+    let numbers = (end - start) as f64 + 1.0;
+    for &method in &options.methods {
+        let mut baseline = None;
+        let thread_counts = iter::once(1).chain(options.thread_counts.iter().copied());
+        for threads in thread_counts {
+            if threads == 1 && baseline.is_some() {
+                continue;
+            }
+            let search = SearchOptions {
+                method,
+                chunk_size: options.chunk_size,
+                ..SearchOptions::new(threads)
+            };
+            let (elapsed, primes) = time_search(start, end, &search, options.runs);
+            let seconds = elapsed.as_secs_f64().max(f64::MIN_POSITIVE);
+            let baseline = *baseline.get_or_insert(seconds);
+            f(&Measurement {
+                method,
+                threads,
+                elapsed,
+                primes,
+                throughput: numbers / seconds,
+                speedup: baseline / seconds,
+            });
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, Instant};
+
+use crate::output::{read_varint, write_varint};
+use crate::search::{self, SearchOptions};
+
+/// Magic bytes at the start of a checkpoint file.
+const MAGIC: &[u8; 4] = b"PCKP";
+const VERSION: u8 = 1;
+const HEADER_LEN: u64 = 4 + 1 + 8 * 3;
+
+/// How often completed chunks are flushed to the checkpoint file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Searches `[start, end]` like `search::find_primes_in_range`, recording every
+/// completed chunk and its primes in the checkpoint file at `path`.
+///
+/// The file is a header followed by one record per chunk, in order: the number
+/// of primes in the chunk and then the gaps between consecutive primes, all as
+/// varints. Records are only appended, and flushed every `FLUSH_INTERVAL`, so
+/// a search which is killed loses at most the chunks since the last flush.
+///
+/// With `resume` the primes already recorded in `path` are passed to `f` first
+/// and the search continues after the last complete record, so the primes seen
+/// by `f` are identical to those of an uninterrupted run. Without it `path`
+/// must not exist yet, so progress is never overwritten by accident.
+pub fn find_primes_in_range<F>(
+    start: u64,
+    end: u64,
+    options: &SearchOptions,
+    path: &str,
+    resume: bool,
+    mut f: F,
+) -> io::Result<()>
+where
+    F: FnMut(u64),
+{
+    let chunk_size = options.chunk_size.max(1);
+    let mut previous = start;
+    let mut chunks: u64 = 0;
+    let mut file = if resume {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = replay(&mut file, start, end, chunk_size, |primes| {
+            chunks += 1;
+            for p in primes {
+                f(*p);
+                previous = *p;
+            }
+        })?;
+        // Drop a record which was only partially written before the search was
+        // interrupted, so new records follow the last complete one.
+        file.set_len(len)?;
+        file.seek(SeekFrom::Start(len))?;
+        file
+    } else {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => io::Error::new(
+                    e.kind(),
+                    format!(
+                        "checkpoint {} already exists, pass --resume to continue it",
+                        path
+                    ),
+                ),
+                _ => e,
+            })?;
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION])?;
+        file.write_all(&start.to_le_bytes())?;
+        file.write_all(&end.to_le_bytes())?;
+        file.write_all(&chunk_size.to_le_bytes())?;
+        file
+    };
+
+    let resume_at = chunks
+        .checked_mul(chunk_size)
+        .and_then(|x| x.checked_add(start))
+        .filter(|x| *x <= end);
+    let resume_at = match resume_at {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+
+    let mut out = BufWriter::new(&mut file);
+    let mut result = Ok(());
+    let mut flushed = Instant::now();
+    search::find_chunks_in_range(resume_at, end, options, |_, primes| {
+        for p in &primes {
+            f(*p);
+        }
+        if result.is_err() {
+            return;
+        }
+        result = write_record(&mut out, previous, &primes);
+        previous = primes.last().copied().unwrap_or(previous);
+        if result.is_ok() && flushed.elapsed() >= FLUSH_INTERVAL {
+            result = out.flush().and_then(|_| out.get_ref().sync_data());
+            flushed = Instant::now();
+        }
+    });
+    result?;
+    out.flush()?;
+    drop(out);
+    file.sync_data()
+}
+
+fn write_record<W: Write>(out: &mut W, mut previous: u64, primes: &[u64]) -> io::Result<()> {
+    write_varint(out, primes.len() as u64)?;
+    for p in primes {
+        write_varint(out, p - previous)?;
+        previous = *p;
+    }
+    Ok(())
+}
+
+/// Reads the checkpoint in `file`, checking that it was written for the same
+/// search, and calls `f` with the primes of each complete record. Returns the
+/// length of the file up to the end of the last complete record.
+fn replay<F>(file: &mut File, start: u64, end: u64, chunk_size: u64, mut f: F) -> io::Result<u64>
+where
+    F: FnMut(&[u64]),
+{
+    let mut input = BufReader::new(file);
+    let mut header = [0; HEADER_LEN as usize];
+    input.read_exact(&mut header)?;
+    if &header[..4] != MAGIC || header[4] != VERSION {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "not a checkpoint file",
+        ));
+    }
+    let field = |i: usize| u64::from_le_bytes(header[5 + i * 8..13 + i * 8].try_into().unwrap());
+    if (field(0), field(1), field(2)) != (start, end, chunk_size) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "checkpoint is for range [{}, {}] with chunk size {}",
+                field(0),
+                field(1),
+                field(2)
+            ),
+        ));
+    }
+
+    let read = Cell::new(HEADER_LEN);
+    let mut bytes = input.bytes().inspect(|_| read.set(read.get() + 1));
+    let mut complete = HEADER_LEN;
+    let mut previous = start;
+    let mut primes = Vec::new();
+    while let Some(count) = read_record_len(&mut bytes)? {
+        primes.clear();
+        for _ in 0..count {
+            match read_varint(&mut bytes) {
+                Ok(Some(delta)) => primes.push(previous + delta),
+                Ok(None) => return Ok(complete),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(complete),
+                Err(e) => return Err(e),
+            }
+            previous = *primes.last().unwrap();
+        }
+        f(&primes);
+        complete = read.get();
+    }
+    Ok(complete)
+}
+
+/// Reads the prime count which starts a record, treating a count cut short by
+/// the end of the file like the end of the file.
+fn read_record_len<I>(input: &mut I) -> io::Result<Option<u64>>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    match read_varint(input) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn search(options: &SearchOptions, path: &str, resume: bool) -> io::Result<Vec<u64>> {
+        let mut primes = Vec::new();
+        find_primes_in_range(1000, 200_000, options, path, resume, |p| primes.push(p))?;
+        Ok(primes)
+    }
+
+    #[test]
+    fn resumed_runs_match_uninterrupted_ones() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("p1-checkpoint-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let options = SearchOptions {
+            chunk_size: 7000,
+            ..SearchOptions::new(2)
+        };
+        let mut expected = Vec::new();
+        search::find_primes_in_range(1000, 200_000, &options, |p| expected.push(p));
+
+        let _ = fs::remove_file(path);
+        assert_eq!(search(&options, path, false).unwrap(), expected);
+        let complete = fs::read(path).unwrap();
+        let err = search(&options, path, false).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(path).unwrap(), complete);
+
+        // Cut the file anywhere, including inside a record, as a kill would.
+        for len in (HEADER_LEN as usize..complete.len()).step_by(97) {
+            fs::write(path, &complete[..len]).unwrap();
+            assert_eq!(search(&options, path, true).unwrap(), expected, "{}", len);
+            assert_eq!(fs::read(path).unwrap(), complete, "{}", len);
+        }
+
+        let other = SearchOptions {
+            chunk_size: 5000,
+            ..options
+        };
+        assert!(search(&other, path, true).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,613 @@
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use netprog_p1::bench::BenchOptions;
+use netprog_p1::distributed::CoordinatorOptions;
+use netprog_p1::factor;
+use netprog_p1::families::{self, Family, Form};
+use netprog_p1::goldbach;
+use netprog_p1::output::Format;
+use netprog_p1::search::{self, Method, SearchOptions};
+use netprog_p1::server::ServerOptions;
+
+pub const USAGE: &str = "\
+Usage: netprog-p1 [COMMAND] [START END [THREADS]] [OPTIONS]
+
+Commands:
+  list                 Print every prime in the range (default)
+  count                Print the number of primes in the range
+  twins                Print every pair of primes p, p + 2
+  cousins              Print every pair of primes p, p + 4
+  sexy                 Print every pair of primes p, p + 6
+  gaps                 Print the record gaps between consecutive primes
+  sum                  Print the sum of the primes in the range
+  sophie-germain       Print every prime p for which 2p + 1 is also prime
+  safe                 Print every prime p for which (p - 1) / 2 is also prime
+  palindromic          Print every prime which reads the same backwards
+  form                 Print every prime of the form given with --form
+  mersenne             Print every exponent p in the range for which 2^p - 1
+                       is prime, by the Lucas-Lehmer test
+  goldbach             Print the smallest prime p for which n - p is also
+                       prime, for every even n in the range, and flag any
+                       counterexample to Goldbach's conjecture
+  factor N | START END Print the prime factors of N or of every number in
+                       the range
+  bench                Time the search of the range with each method and
+                       thread count, as a table or with --format csv
+  isprime N            Print whether N is prime
+  build-table FILE     Save a table of the primes up to --end to FILE
+  decode FILE          Print the primes stored in a binary prime file
+  worker ADDR          Search chunks handed out by the coordinator at ADDR
+  serve ADDR           Answer ISPRIME n, NEXT n, PREV n, COUNT a b and
+                       RANGE a b requests from clients connecting to ADDR
+
+Options:
+  --start N            First number of the range [default: 0]
+  --end N              Last number of the range
+  --threads N          Number of threads [default: available cores]
+  --method METHOD      auto, sieve, mr or trial [default: auto]
+  --chunk-size N       Numbers handed to a thread at a time
+  --form A*n+B         Form of the primes for the form command
+  --format FORMAT      line, text, json, csv or binary [default: line]
+  --output FILE        Write to FILE instead of standard output
+  --table FILE         Answer count and isprime from a table saved with
+                       build-table
+  --checkpoint FILE    Record progress in FILE while searching
+  --resume             Continue the search recorded in the checkpoint
+  --coordinate ADDR    Hand the search out to workers connecting to ADDR
+  --time-limit SECONDS Stop searching after SECONDS and print the results for
+                       the part of the range searched so far, as on Ctrl-C.
+                       Not available with --format binary
+  --timeout SECONDS    Time a worker has to return a chunk [default: 60]
+  --methods LIST       Comma-separated methods to bench [default: sieve,mr]
+  --thread-counts LIST Comma-separated thread counts to bench
+                       [default: powers of two up to --threads]
+  --runs N             Times to run each bench, keeping the fastest
+                       [default: 1]
+  --verify             Search the range with a second method instead,
+                       reporting every number the two disagree on and
+                       checking pi(10^k) against known values
+  --summary            Only print a summary of the goldbach check
+  --cache N            Numbers the server sieves on start [default: 2^24]
+  --cache-limit N      Numbers the server's cache may grow to
+                       [default: 2^32]
+  --progress           Show progress and ETA on standard error
+  -h, --help           Print this help
+";
+
+/// Options which take a value.
+const OPTIONS: [&str; 18] = [
+    "--start",
+    "--end",
+    "--threads",
+    "--method",
+    "--chunk-size",
+    "--form",
+    "--format",
+    "--output",
+    "--table",
+    "--checkpoint",
+    "--coordinate",
+    "--timeout",
+    "--time-limit",
+    "--cache",
+    "--cache-limit",
+    "--methods",
+    "--thread-counts",
+    "--runs",
+];
+
+/// Options which don't take a value.
+const FLAGS: [&str; 5] = ["--resume", "--verify", "--summary", "--progress", "--help"];
+
+/// What to do.
+pub enum Command {
+    Help,
+    /// Print every prime.
+    List,
+    /// Print the number of primes.
+    Count,
+    /// Print every pair of primes this far apart.
+    Pairs(u64),
+    /// Print the record gaps between consecutive primes.
+    Gaps,
+    /// Print the sum of the primes.
+    Sum,
+    /// Print every prime of the family.
+    Family(Family),
+    /// Print the minimal Goldbach decomposition of every even number.
+    Goldbach,
+    /// Print the prime factors of every number.
+    Factor,
+    /// Time searches with different methods and thread counts.
+    Bench,
+    /// Print whether the number is prime.
+    IsPrime(u64),
+    /// Save a table of the primes to the path.
+    BuildTable(String),
+    /// Print the primes stored in the binary prime file at the path.
+    Decode(String),
+    /// Work for the coordinator at the address.
+    Worker(String),
+    /// Answer prime queries from clients connecting to the address.
+    Serve(String),
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "list" => Some(Command::List),
+            "count" => Some(Command::Count),
+            "twins" => Some(Command::Pairs(2)),
+            "cousins" => Some(Command::Pairs(4)),
+            "sexy" => Some(Command::Pairs(6)),
+            "gaps" => Some(Command::Gaps),
+            "sum" => Some(Command::Sum),
+            "sophie-germain" => Some(Command::Family(Family::SophieGermain)),
+            "safe" => Some(Command::Family(Family::Safe)),
+            "palindromic" => Some(Command::Family(Family::Palindromic)),
+            // The form itself is given with --form.
+            "form" => Some(Command::Family(Family::Form(Form { a: 1, b: 0 }))),
+            "mersenne" => Some(Command::Family(Family::Mersenne)),
+            "goldbach" => Some(Command::Goldbach),
+            "factor" => Some(Command::Factor),
+            "bench" => Some(Command::Bench),
+            "isprime" => Some(Command::IsPrime(0)),
+            "build-table" => Some(Command::BuildTable(String::new())),
+            "decode" => Some(Command::Decode(String::new())),
+            "worker" => Some(Command::Worker(String::new())),
+            "serve" => Some(Command::Serve(String::new())),
+            _ => None,
+        }
+    }
+}
+
+/// Everything given on the command line.
+pub struct Config {
+    pub command: Command,
+    pub start: u64,
+    pub end: u64,
+    pub options: SearchOptions,
+    pub format: Format,
+    pub output: Option<String>,
+    pub table: Option<String>,
+    pub checkpoint: Option<String>,
+    pub resume: bool,
+    pub coordinate: Option<String>,
+    pub timeout: Duration,
+    pub time_limit: Option<Duration>,
+    pub verify: bool,
+    pub summary: bool,
+    pub server: ServerOptions,
+    pub bench: BenchOptions,
+    pub progress: bool,
+}
+
+impl Config {
+    pub fn coordinator_options(&self) -> CoordinatorOptions {
+        CoordinatorOptions {
+            method: self.options.method,
+            chunk_size: self.options.chunk_size,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// Comma-separated values of an option.
+struct List<T>(Vec<T>);
+
+impl<T: FromStr> FromStr for List<T> {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<List<T>, ()> {
+        let values: Result<Vec<T>, _> = s.split(',').map(|x| x.trim().parse()).collect();
+        values.map(List).map_err(|_| ())
+    }
+}
+
+/// Arguments split into positional arguments and `--name value` options.
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+    /// Names of the options already taken out of `options`.
+    used: Vec<String>,
+}
+
+impl Args {
+    fn split<I: Iterator<Item = String>>(mut args: I) -> Result<Args, String> {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        while let Some(arg) = args.next() {
+            if arg == "-h" {
+                options.push(("--help".to_string(), None));
+                continue;
+            }
+            if !arg.starts_with("--") {
+                positional.push(arg);
+                continue;
+            }
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            if FLAGS.contains(&name.as_str()) {
+                options.push((name, value));
+            } else if !OPTIONS.contains(&name.as_str()) {
+                return Err(format!("unknown option '{}'", name));
+            } else if value.is_some() {
+                options.push((name, value));
+            } else {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("{} requires a value", name))?;
+                options.push((name, Some(value)));
+            }
+        }
+        Ok(Args {
+            positional,
+            options,
+            used: Vec::new(),
+        })
+    }
+
+    /// Removes and returns the value of the option `name`.
+    fn value<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, String> {
+        let index = match self.options.iter().position(|x| x.0 == name) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        self.used.push(name.to_string());
+        match self.options.remove(index).1 {
+            Some(value) => parse(name, &value).map(Some),
+            None => Err(format!("{} requires a value", name)),
+        }
+    }
+
+    /// Removes the flag `name`, returning whether it was given.
+    fn flag(&mut self, name: &str) -> Result<bool, String> {
+        let index = match self.options.iter().position(|x| x.0 == name) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        self.used.push(name.to_string());
+        match self.options.remove(index).1 {
+            Some(_) => Err(format!("{} does not take a value", name)),
+            None => Ok(true),
+        }
+    }
+
+    /// Returns the value given either as the `index`th positional argument or
+    /// as the option `name`, but not both.
+    fn either<T: FromStr>(&mut self, index: usize, name: &str) -> Result<Option<T>, String> {
+        let named = self.value(name)?;
+        match self.positional.get(index) {
+            Some(_) if named.is_some() => Err(format!("{} given twice", name)),
+            Some(value) => parse(name, value).map(Some),
+            None => Ok(named),
+        }
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, name))
+}
+
+/// Parses the command line arguments, not including the program name.
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Config, String> {
+    let mut args = Args::split(args)?;
+    let mut name = "list".to_string();
+    let mut command = match args.positional.first() {
+        Some(given) => match Command::from_name(given) {
+            Some(command) => {
+                name = args.positional.remove(0);
+                command
+            }
+            None if given.parse::<u64>().is_ok() => Command::List,
+            None => return Err(format!("unknown command '{}'", given)),
+        },
+        None => Command::List,
+    };
+    if args.flag("--help")? {
+        command = Command::Help;
+    }
+
+    let (mut start, mut end, mut threads) = (0, 0, None);
+    match &mut command {
+        Command::Help => {}
+        Command::Decode(target) | Command::Worker(target) | Command::Serve(target) => {
+            if args.positional.len() != 1 {
+                return Err("expected exactly one FILE or ADDR argument".to_string());
+            }
+            *target = args.positional.remove(0);
+            threads = args.value("--threads")?;
+        }
+        Command::BuildTable(path) => {
+            if args.positional.len() != 1 {
+                return Err("expected exactly one FILE argument".to_string());
+            }
+            *path = args.positional.remove(0);
+            end = args
+                .value("--end")?
+                .ok_or("the end of the table is required (--end)")?;
+            threads = args.value("--threads")?;
+        }
+        Command::IsPrime(n) => {
+            if args.positional.len() != 1 {
+                return Err("expected exactly one number N".to_string());
+            }
+            *n = parse("N", &args.positional[0])?;
+        }
+        _ => {
+            if args.positional.len() > 3 {
+                return Err(format!("unexpected argument '{}'", args.positional[3]));
+            }
+            start = args.either(0, "--start")?.unwrap_or(0);
+            end = match args.either(1, "--end")? {
+                Some(end) => end,
+                // `factor N` factors just N.
+                None if matches!(command, Command::Factor) && args.positional.len() == 1 => start,
+                None => return Err("the end of the range is required (END or --end)".to_string()),
+            };
+            threads = args.either(2, "--threads")?;
+            if start > end {
+                return Err(format!(
+                    "start of the range ({}) is after its end ({})",
+                    start, end
+                ));
+            }
+        }
+    }
+
+    match (&mut command, args.value("--form")?) {
+        (Command::Family(Family::Form(form)), Some(given)) => *form = given,
+        (Command::Family(Family::Form(_)), None) => {
+            return Err("the form command requires --form".to_string())
+        }
+        (_, Some(_)) => return Err("--form is only used by the form command".to_string()),
+        _ => {}
+    }
+
+    let threads = match threads {
+        Some(0) => return Err("cannot use 0 threads".to_string()),
+        Some(threads) => threads,
+        None => thread::available_parallelism().map_or(1, |x| x.get() as u64),
+    };
+    let chunk_size = args.value("--chunk-size")?.unwrap_or(match command {
+        Command::Factor => factor::DEFAULT_CHUNK_SIZE,
+        Command::Goldbach => goldbach::DEFAULT_CHUNK_SIZE,
+        Command::Family(Family::Mersenne) => families::MERSENNE_CHUNK_SIZE,
+        _ => search::DEFAULT_CHUNK_SIZE,
+    });
+    if chunk_size == 0 {
+        return Err("chunk size must be at least 1".to_string());
+    }
+    let options = SearchOptions {
+        method: args.value::<Method>("--method")?.unwrap_or(Method::Auto),
+        chunk_size,
+        ..SearchOptions::new(threads)
+    };
+
+    let (cache, cache_limit) = (args.value("--cache")?, args.value("--cache-limit")?);
+    if (cache.is_some() || cache_limit.is_some()) && !matches!(command, Command::Serve(_)) {
+        return Err("--cache and --cache-limit are only used by the serve command".to_string());
+    }
+    let defaults = ServerOptions::new(threads);
+    let server = ServerOptions {
+        cache: cache.unwrap_or(defaults.cache),
+        cache_limit: cache_limit.unwrap_or(defaults.cache_limit),
+        ..defaults
+    };
+
+    let methods = args.value::<List<Method>>("--methods")?;
+    let thread_counts = args.value::<List<u64>>("--thread-counts")?;
+    let runs = args.value("--runs")?;
+    if runs == Some(0) {
+        return Err("--runs must be at least 1".to_string());
+    }
+    let bench_options = methods.is_some() || thread_counts.is_some() || runs.is_some();
+    if bench_options && !matches!(command, Command::Bench) {
+        return Err("--methods, --thread-counts and --runs are only used by bench".to_string());
+    }
+    if thread_counts.as_ref().is_some_and(|x| x.0.contains(&0)) {
+        return Err("cannot use 0 threads".to_string());
+    }
+    let defaults = BenchOptions::new(threads);
+    let bench = BenchOptions {
+        methods: methods.map_or(defaults.methods, |x| x.0),
+        thread_counts: thread_counts.map_or(defaults.thread_counts, |x| x.0),
+        chunk_size,
+        runs: runs.unwrap_or(defaults.runs),
+    };
+
+    // Only the commands which honour these options read them, so anywhere else
+    // they are reported as unused below.
+    let format = match command {
+        Command::List | Command::Family(_) | Command::Bench => args.value("--format")?,
+        _ => None,
+    };
+    let output = match command {
+        Command::Help
+        | Command::Decode(_)
+        | Command::Worker(_)
+        | Command::Serve(_)
+        | Command::IsPrime(_)
+        | Command::BuildTable(_) => None,
+        _ => args.value("--output")?,
+    };
+    let table = match command {
+        Command::Count | Command::IsPrime(_) => args.value("--table")?,
+        _ => None,
+    };
+
+    let timeout = args.value("--timeout")?;
+    let config = Config {
+        command,
+        start,
+        end,
+        options,
+        format: format.unwrap_or(Format::Line),
+        output,
+        table,
+        checkpoint: args.value("--checkpoint")?,
+        resume: args.flag("--resume")?,
+        coordinate: args.value("--coordinate")?,
+        timeout: match timeout {
+            Some(secs) => Duration::from_secs(secs),
+            None => CoordinatorOptions::default().timeout,
+        },
+        verify: args.flag("--verify")?,
+        summary: args.flag("--summary")?,
+        server,
+        bench,
+        time_limit: args.value("--time-limit")?.map(Duration::from_secs),
+        progress: args.flag("--progress")?,
+    };
+    // Unknown options are rejected while splitting, so any left over were
+    // either given twice or aren't used by the command.
+    match args.options.first() {
+        _ if matches!(config.command, Command::Help) => {}
+        Some((option, _)) if args.used.contains(option) => {
+            return Err(format!("{} given twice", option));
+        }
+        Some((option, _)) => {
+            return Err(format!("{} is not used by the {} command", option, name));
+        }
+        None => {}
+    }
+    if timeout.is_some() && config.coordinate.is_none() {
+        return Err("--timeout is only used with --coordinate".to_string());
+    }
+    if config.resume && config.checkpoint.is_none() {
+        return Err("--resume requires --checkpoint".to_string());
+    }
+    if config.coordinate.is_some() && config.checkpoint.is_some() {
+        return Err("--coordinate cannot be combined with --checkpoint".to_string());
+    }
+    let own_search = matches!(
+        config.command,
+        Command::Family(_) | Command::Goldbach | Command::Factor
+    );
+    if own_search && (config.checkpoint.is_some() || config.coordinate.is_some()) {
+        return Err("only prime searches support --checkpoint and --coordinate".to_string());
+    }
+    let searches = config.verify
+        || config.checkpoint.is_some()
+        || config.coordinate.is_some()
+        || config.time_limit.is_some();
+    if config.table.is_some() && searches {
+        return Err(
+            "--table cannot be combined with --verify, --checkpoint, --coordinate or --time-limit"
+                .to_string(),
+        );
+    }
+    let prime_search = matches!(
+        config.command,
+        Command::List | Command::Count | Command::Pairs(_) | Command::Gaps | Command::Sum
+    );
+    if config.verify
+        && (!prime_search || config.checkpoint.is_some() || config.coordinate.is_some())
+    {
+        return Err("--verify only checks plain prime searches".to_string());
+    }
+    if config.verify && format.is_some() {
+        return Err("--verify prints a report, which has no --format".to_string());
+    }
+    if matches!(config.command, Command::Bench)
+        && !matches!(config.format, Format::Line | Format::Text | Format::Csv)
+    {
+        return Err("bench prints a table or, with --format csv, CSV".to_string());
+    }
+    let plain = config.checkpoint.is_none() && config.coordinate.is_none();
+    if config.time_limit.is_some() && !(prime_search && plain && !config.verify) {
+        return Err("--time-limit only applies to plain prime searches".to_string());
+    }
+    if config.time_limit.is_some() && config.format == Format::Binary {
+        return Err("--time-limit cannot be used with --format binary".to_string());
+    }
+    if config.summary && !matches!(config.command, Command::Goldbach) {
+        return Err("--summary is only used by the goldbach command".to_string());
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Config, String> {
+        parse_args(line.split_whitespace().map(String::from))
+    }
+
+    fn error(line: &str) -> String {
+        match parse_line(line) {
+            Ok(_) => panic!("'{}' was accepted", line),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn accepts_options_the_command_uses() {
+        let config = parse_line("list 0 10 --format json --output f").unwrap();
+        assert_eq!(config.format, Format::Json);
+        assert_eq!(config.output.as_deref(), Some("f"));
+        let config = parse_line("count 0 10 --table t --output f").unwrap();
+        assert_eq!(config.table.as_deref(), Some("t"));
+        for line in [
+            "isprime 7 --table t",
+            "sophie-germain 0 100 --format csv",
+            "bench 0 100 --format csv --output f",
+            "goldbach 4 100 --output f",
+            "list 0 100 --verify --output f",
+        ] {
+            assert!(parse_line(line).is_ok(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn rejects_options_the_command_ignores() {
+        for (line, option, command) in [
+            (
+                "count 0 10 --format binary --output y.bin",
+                "--format",
+                "count",
+            ),
+            ("factor 10 --format json", "--format", "factor"),
+            ("goldbach 4 10 --format json", "--format", "goldbach"),
+            ("gaps 0 10 --format json", "--format", "gaps"),
+            ("isprime 7 --output f", "--output", "isprime"),
+            ("decode f --output g", "--output", "decode"),
+            ("list 0 10 --table t", "--table", "list"),
+            ("isprime 7 --threads 2", "--threads", "isprime"),
+        ] {
+            let expected = format!("{} is not used by the {} command", option, command);
+            assert_eq!(error(line), expected, "{}", line);
+        }
+        assert_eq!(
+            error("count 0 10 --output a --output b"),
+            "--output given twice"
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_options() {
+        assert!(error("count 0 10 --table t --verify").starts_with("--table cannot"));
+        assert!(error("count 0 10 --table t --checkpoint c").starts_with("--table cannot"));
+        assert!(error("list 0 10 --verify --format json").starts_with("--verify"));
+        assert_eq!(
+            error("list 0 10 --resume"),
+            "--resume requires --checkpoint"
+        );
+        assert_eq!(error("list 0 10 --runs 0"), "--runs must be at least 1");
+        assert_eq!(
+            error("list 0 10 --time-limit 5 --format binary"),
+            "--time-limit cannot be used with --format binary"
+        );
+        assert_eq!(
+            error("list 0 10 --timeout 5"),
+            "--timeout is only used with --coordinate"
+        );
+    }
+}
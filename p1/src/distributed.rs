@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::search::{self, Method, SearchOptions, DEFAULT_CHUNK_SIZE};
+
+/// Number of chunks per connected worker which may be handed out ahead of the
+/// ordered emission before the coordinator stops handing out new ones.
+const CHUNKS_IN_FLIGHT: u64 = 4;
+
+/// How often the coordinator checks whether the search is finished while
+/// waiting for workers to connect.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Tunables of the coordinator.
+#[derive(Clone, Copy, Debug)]
+pub struct CoordinatorOptions {
+    /// Method the workers use to search their chunks.
+    pub method: Method,
+    /// Amount of the number line handed to a worker at a time.
+    pub chunk_size: u64,
+    /// How long a worker may take to return a chunk before it is considered
+    /// lost and the chunk is handed to another worker.
+    pub timeout: Duration,
+}
+
+impl Default for CoordinatorOptions {
+    fn default() -> CoordinatorOptions {
+        CoordinatorOptions {
+            method: Method::Auto,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+struct AssignmentState {
+    /// Index of the next chunk which has never been handed out.
+    next: u64,
+    /// Chunks taken back from workers which failed before returning them.
+    returned: BTreeSet<u64>,
+    /// Number of chunks which have been emitted, in order.
+    emitted: u64,
+    /// Number of workers currently connected.
+    workers: u64,
+}
+
+/// Keeps track of which chunks still have to be handed out to workers.
+struct Assignments {
+    chunks: u64,
+    state: Mutex<AssignmentState>,
+    cvar: Condvar,
+}
+
+impl Assignments {
+    fn new(chunks: u64) -> Assignments {
+        Assignments {
+            chunks,
+            state: Mutex::new(AssignmentState {
+                next: 0,
+                returned: BTreeSet::new(),
+                emitted: 0,
+                workers: 0,
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+
+    /// Takes the next chunk to hand out, preferring chunks given back by failed
+    /// workers. Blocks while nothing can be handed out, and returns `None` once
+    /// every chunk has been emitted.
+    fn take(&self) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(chunk) = state.returned.pop_first() {
+                return Some(chunk);
+            }
+            if state.emitted == self.chunks {
+                return None;
+            }
+            if state.next < self.chunks
+                && state.next < state.emitted + state.workers * CHUNKS_IN_FLIGHT
+            {
+                state.next += 1;
+                return Some(state.next - 1);
+            }
+            state = self.cvar.wait(state).unwrap();
+        }
+    }
+
+    /// Hands `chunk` back after the worker it was given to failed.
+    fn give_back(&self, chunk: u64) {
+        self.state.lock().unwrap().returned.insert(chunk);
+        self.cvar.notify_one();
+    }
+
+    fn set_emitted(&self, emitted: u64) {
+        self.state.lock().unwrap().emitted = emitted;
+        self.cvar.notify_all();
+    }
+
+    fn set_workers(&self, delta: i64) {
+        let mut state = self.state.lock().unwrap();
+        state.workers = state.workers.saturating_add_signed(delta);
+        self.cvar.notify_all();
+    }
+
+    fn finished(&self) -> bool {
+        self.state.lock().unwrap().emitted == self.chunks
+    }
+}
+
+/// Searches `[start, end]` by handing chunks to the workers which connect to
+/// `listener`, and calls `f` with every prime in ascending order.
+///
+/// Each worker is given one chunk at a time. A worker which disconnects, sends
+/// something invalid or doesn't return its chunk within `options.timeout` is
+/// dropped and its chunk handed to the next worker asking for one. Returns once
+/// every chunk has been emitted, after telling the connected workers to quit.
+pub fn run_coordinator<F>(
+    listener: TcpListener,
+    start: u64,
+    end: u64,
+    options: &CoordinatorOptions,
+    mut f: F,
+) -> io::Result<()>
+where
+    F: FnMut(u64),
+{
+    if start > end {
+        return Ok(());
+    }
+    let chunk_size = options.chunk_size.max(1);
+    let chunks = (end - start) / chunk_size + 1;
+    let assignments = Assignments::new(chunks);
+    let (tx, rx) = mpsc::channel::<(u64, Vec<u64>)>();
+    listener.set_nonblocking(true)?;
+
+    thread::scope(|scope| {
+        let assignments = &assignments;
+        scope.spawn(move || {
+            while !assignments.finished() {
+                let (stream, addr) = match listener.accept() {
+                    Ok(x) => x,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        thread::sleep(ACCEPT_POLL_INTERVAL);
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to accept worker: {}", e);
+                        continue;
+                    }
+                };
+                eprintln!("Worker {} connected", addr);
+                let tx = tx.clone();
+                scope.spawn(move || {
+                    assignments.set_workers(1);
+                    let worker = Worker {
+                        start,
+                        end,
+                        chunk_size,
+                        options,
+                        assignments,
+                    };
+                    match worker.serve(stream, tx) {
+                        Ok(()) => eprintln!("Worker {} finished", addr),
+                        Err(e) => eprintln!("Worker {} dropped: {}", addr, e),
+                    }
+                    assignments.set_workers(-1);
+                });
+            }
+        });
+
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        while next < chunks {
+            let (chunk, primes) = rx.recv().unwrap();
+            if chunk >= next {
+                pending.insert(chunk, primes);
+            }
+            while let Some(primes) = pending.remove(&next) {
+                primes.into_iter().for_each(&mut f);
+                next += 1;
+                assignments.set_emitted(next);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The coordinator's side of the connection to one worker.
+struct Worker<'a> {
+    start: u64,
+    end: u64,
+    chunk_size: u64,
+    options: &'a CoordinatorOptions,
+    assignments: &'a Assignments,
+}
+
+impl Worker<'_> {
+    fn serve(&self, stream: TcpStream, tx: Sender<(u64, Vec<u64>)>) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.options.timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let mut line = String::new();
+        while let Some(chunk) = self.assignments.take() {
+            let lo = self.start + chunk * self.chunk_size;
+            let hi = self.end.min(lo.saturating_add(self.chunk_size - 1));
+            line.clear();
+            let result = writeln!(
+                writer,
+                "CHUNK {} {} {} {}",
+                chunk, lo, hi, self.options.method
+            )
+            .and_then(|_| reader.read_line(&mut line))
+            .and_then(|_| parse_result(&line, chunk, lo, hi));
+            match result {
+                // The coordinator stops receiving once everything is emitted,
+                // by which point this chunk is no longer needed.
+                Ok(primes) => _ = tx.send((chunk, primes)),
+                Err(e) => {
+                    self.assignments.give_back(chunk);
+                    return Err(e);
+                }
+            }
+        }
+        writeln!(writer, "DONE")
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Parses a `RESULT <chunk> <primes...>` line, checking that it answers
+/// `chunk` and that the primes are ascending and within `[lo, hi]`.
+fn parse_result(line: &str, chunk: u64, lo: u64, hi: u64) -> io::Result<Vec<u64>> {
+    if line.is_empty() {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    let mut words = line.split_whitespace();
+    if words.next() != Some("RESULT") || words.next() != Some(chunk.to_string().as_str()) {
+        return Err(invalid("expected RESULT for the chunk handed out"));
+    }
+    let mut primes = Vec::new();
+    for word in words {
+        let p: u64 = word.parse().map_err(|_| invalid("prime is not a number"))?;
+        if p < lo || p > hi || primes.last().is_some_and(|x| *x >= p) {
+            return Err(invalid("primes out of order or outside the chunk"));
+        }
+        primes.push(p);
+    }
+    Ok(primes)
+}
+
+/// Connects to the coordinator at `addr` and searches the chunks it hands out
+/// with `threads` threads until it says there are none left. Returns the
+/// number of chunks searched.
+pub fn run_worker<A: ToSocketAddrs>(addr: A, threads: u64) -> io::Result<u64> {
+    let stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut chunks = 0;
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["CHUNK", chunk, lo, hi, method] => {
+                let parse = |x: &str| x.parse::<u64>().map_err(|_| invalid("bad CHUNK"));
+                let (lo, hi) = (parse(lo)?, parse(hi)?);
+                if lo > hi {
+                    return Err(invalid("bad CHUNK range"));
+                }
+                // Split the chunk so that every thread gets a part of it.
+                let options = SearchOptions {
+                    method: method.parse().map_err(|_| invalid("bad CHUNK method"))?,
+                    chunk_size: (hi - lo) / threads.max(1) + 1,
+                    ..SearchOptions::new(threads)
+                };
+                write!(writer, "RESULT {}", parse(chunk)?)?;
+                let mut result = Ok(());
+                search::find_primes_in_range(lo, hi, &options, |p| {
+                    if result.is_ok() {
+                        result = write!(writer, " {}", p);
+                    }
+                });
+                result?;
+                writeln!(writer)?;
+                writer.flush()?;
+                chunks += 1;
+            }
+            ["DONE"] => return Ok(chunks),
+            _ => return Err(invalid("unexpected message from coordinator")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workers_on_loopback_find_every_prime() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (start, end) = (1_000, 300_000);
+        let options = CoordinatorOptions {
+            chunk_size: 10_000,
+            timeout: Duration::from_secs(10),
+            ..CoordinatorOptions::default()
+        };
+        let coordinator = thread::spawn(move || {
+            let mut primes = Vec::new();
+            run_coordinator(listener, start, end, &options, |p| primes.push(p)).unwrap();
+            primes
+        });
+
+        // A worker which disconnects after being handed a chunk, which then
+        // has to be searched by one of the others.
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).unwrap();
+        assert!(line.starts_with("CHUNK "));
+        drop(stream);
+
+        let workers: Vec<_> = (1..=2)
+            .map(|threads| thread::spawn(move || run_worker(addr, threads)))
+            .collect();
+        let primes = coordinator.join().unwrap();
+        let chunks: u64 = workers
+            .into_iter()
+            .map(|x| x.join().unwrap().unwrap_or(0))
+            .sum();
+        assert_eq!(chunks, (end - start) / options.chunk_size + 1);
+
+        let mut expected = Vec::new();
+        search::find_primes_in_range(start, end, &SearchOptions::new(1), |p| expected.push(p));
+        assert_eq!(primes, expected);
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::OnceLock;
+
+use crate::primality::{is_prime, mul_mod};
+use crate::search::{self, SearchOptions};
+use crate::sieve;
+
+/// Factors below this are removed by trial division before Pollard's rho.
+const TRIAL_LIMIT: u32 = 1 << 12;
+
+/// Default amount of the number line handed to a thread at a time when
+/// factoring a range. Much smaller than for prime searches since every number
+/// in the chunk produces output.
+pub const DEFAULT_CHUNK_SIZE: u64 = 1 << 14;
+
+/// Number of steps of Brent's cycle search between gcd computations.
+const BATCH: u64 = 128;
+
+fn small_primes() -> &'static [u32] {
+    static PRIMES: OnceLock<Vec<u32>> = OnceLock::new();
+    PRIMES.get_or_init(|| sieve::base_primes(TRIAL_LIMIT))
+}
+
+/// Returns the prime factors of `n` in ascending order, repeated according to
+/// their multiplicity. 0 and 1 have no prime factors.
+pub fn factor(mut n: u64) -> Vec<u64> {
+    let mut factors = Vec::new();
+    if n < 2 {
+        return factors;
+    }
+
+    for &p in small_primes() {
+        let p = p as u64;
+        if p * p > n {
+            break;
+        }
+        while n.is_multiple_of(p) {
+            factors.push(p);
+            n /= p;
+        }
+    }
+
+    let mut stack = vec![n];
+    while let Some(n) = stack.pop() {
+        if n == 1 {
+            continue;
+        }
+        if is_prime(n) {
+            factors.push(n);
+            continue;
+        }
+        let d = pollard_brent(n);
+        stack.push(d);
+        stack.push(n / d);
+    }
+    factors.sort_unstable();
+    factors
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Finds a non-trivial factor of the odd composite `n` with Brent's variant of
+/// Pollard's rho, retrying with another polynomial when a cycle yields none.
+fn pollard_brent(n: u64) -> u64 {
+    for c in 1u64.. {
+        let step = |v: u64| ((mul_mod(v, v, n) as u128 + c as u128) % n as u128) as u64;
+        let (mut x, mut y, mut ys) = (0, 2, 0);
+        let (mut g, mut r, mut q) = (1, 1, 1);
+
+        while g == 1 {
+            x = y;
+            for _ in 0..r {
+                y = step(y);
+            }
+            let mut k = 0;
+            while k < r && g == 1 {
+                ys = y;
+                for _ in 0..BATCH.min(r - k) {
+                    y = step(y);
+                    q = mul_mod(q, x.abs_diff(y), n);
+                }
+                g = gcd(q, n);
+                k += BATCH;
+            }
+            r *= 2;
+        }
+
+        // The batch overshot; retrace it one step at a time.
+        if g == n {
+            loop {
+                ys = step(ys);
+                g = gcd(x.abs_diff(ys), n);
+                if g > 1 {
+                    break;
+                }
+            }
+        }
+        if g != n {
+            return g;
+        }
+    }
+    unreachable!()
+}
+
+/// Factors every number in `[start, end]` on the search threads and calls `f`
+/// with each number and its prime factors, in ascending order of the numbers.
+pub fn factor_range<F>(start: u64, end: u64, options: &SearchOptions, mut f: F)
+where
+    F: FnMut(u64, Vec<u64>),
+{
+    let factor_chunk = |lo, hi| (lo..=hi).map(factor).collect::<Vec<_>>();
+    search::map_chunks_in_range(start, end, options, factor_chunk, |chunk, factors| {
+        let lo = start + chunk * options.chunk_size.max(1);
+        for (n, factors) in (lo..=end).zip(factors) {
+            f(n, factors);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primes_have_themselves_as_factor() {
+        for p in [2, 3, 4093, 4099, 4294967291, 18446744073709551557] {
+            assert_eq!(factor(p), [p]);
+        }
+        assert_eq!(factor(0), []);
+        assert_eq!(factor(1), []);
+    }
+
+    #[test]
+    fn semiprimes_and_squares() {
+        let p = 4294967291;
+        assert_eq!(factor(p * p), [p, p]);
+        assert_eq!(factor(4099 * 4099), [4099, 4099]);
+        assert_eq!(factor(4294967279 * 4294967291), [4294967279, 4294967291]);
+        assert_eq!(
+            factor(1_000_000_007 * 998_244_353),
+            [998_244_353, 1_000_000_007]
+        );
+        assert_eq!(factor(2 * 9223372036854775783), [2, 9223372036854775783]);
+        assert_eq!(factor(1 << 63), [2; 63]);
+    }
+
+    #[test]
+    fn u64_max() {
+        let factors = [3, 5, 17, 257, 641, 65537, 6700417];
+        assert_eq!(factor(u64::MAX), factors);
+    }
+
+    #[test]
+    fn factors_multiply_back() {
+        for n in (0..2000).chain(u64::MAX - 2000..=u64::MAX) {
+            let factors = factor(n);
+            assert!(factors.iter().all(|p| is_prime(*p)), "{}", n);
+            if n > 1 {
+                assert_eq!(factors.iter().product::<u64>(), n);
+            }
+        }
+    }
+
+    #[test]
+    fn ranges_ending_at_u64_max() {
+        let options = SearchOptions {
+            chunk_size: 7,
+            ..SearchOptions::new(2)
+        };
+        for start in [u64::MAX, u64::MAX - 1, u64::MAX - 20] {
+            let mut numbers = Vec::new();
+            factor_range(start, u64::MAX, &options, |n, factors| {
+                assert_eq!(factors, factor(n));
+                numbers.push(n);
+            });
+            assert_eq!(numbers, (start..=u64::MAX).collect::<Vec<_>>());
+        }
+    }
+}
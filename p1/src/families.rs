@@ -0,0 +1,338 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::primality::is_prime;
+use crate::search::{self, ChunkSearcher, SearchOptions};
+
+/// Default amount of exponents handed to a thread at a time when searching for
+/// Mersenne primes. Each Lucas-Lehmer test of a large exponent takes far longer
+/// than sieving a whole chunk of a prime search.
+pub const MERSENNE_CHUNK_SIZE: u64 = 16;
+
+/// A structured family of primes to search for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Family {
+    /// Primes p for which 2p + 1 is also prime.
+    SophieGermain,
+    /// Primes p for which (p - 1) / 2 is also prime.
+    Safe,
+    /// Primes which read the same backwards in decimal.
+    Palindromic,
+    /// Primes of the given form.
+    Form(Form),
+    /// Exponents p for which the Mersenne number 2^p - 1 is prime.
+    Mersenne,
+}
+
+impl Family {
+    /// Returns whether the prime `p` belongs to the family.
+    pub fn contains(self, p: u64) -> bool {
+        match self {
+            Family::SophieGermain => p
+                .checked_mul(2)
+                .and_then(|v| v.checked_add(1))
+                .is_some_and(is_prime),
+            Family::Safe => p > 2 && is_prime((p - 1) / 2),
+            Family::Palindromic => is_palindrome(p),
+            Family::Form(form) => form.contains(p),
+            Family::Mersenne => lucas_lehmer(p),
+        }
+    }
+}
+
+/// The form a * n + b, n >= 0, of the numbers in an arithmetic progression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Form {
+    pub a: u64,
+    pub b: u64,
+}
+
+impl Form {
+    /// Returns whether `v` is a * n + b for some n >= 0.
+    pub fn contains(self, v: u64) -> bool {
+        match self.a {
+            0 => v == self.b,
+            a => v >= self.b && (v - self.b).is_multiple_of(a),
+        }
+    }
+}
+
+/// Parses `a*n+b`, also accepting `an+b`, `a*n` and `n+b`.
+impl FromStr for Form {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Form, ()> {
+        let (a, b) = match s.split_once('+') {
+            Some((a, b)) => (a, b.parse().map_err(|_| ())?),
+            None => (s, 0),
+        };
+        let a = match a.strip_suffix('n').ok_or(())? {
+            "" => 1,
+            a => a.strip_suffix('*').unwrap_or(a).parse().map_err(|_| ())?,
+        };
+        Ok(Form { a, b })
+    }
+}
+
+impl fmt::Display for Form {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}*n+{}", self.a, self.b)
+    }
+}
+
+fn is_palindrome(v: u64) -> bool {
+    let (mut rest, mut reversed) = (v, 0u128);
+    while rest > 0 {
+        reversed = reversed * 10 + (rest % 10) as u128;
+        rest /= 10;
+    }
+    reversed == v as u128
+}
+
+/// Returns whether the Mersenne number 2^p - 1 is prime, using the
+/// Lucas-Lehmer test for odd prime exponents.
+pub fn lucas_lehmer(p: u64) -> bool {
+    if p == 2 {
+        return true;
+    }
+    if !is_prime(p) {
+        return false;
+    }
+    let mut s = Mersenne::new(p, 4);
+    for _ in 0..p - 2 {
+        s.square();
+        s.sub_two();
+    }
+    s.is_zero()
+}
+
+/// A residue modulo 2^p - 1, stored as little-endian limbs of p bits in total.
+struct Mersenne {
+    p: u64,
+    limbs: Vec<u64>,
+}
+
+impl Mersenne {
+    fn new(p: u64, v: u64) -> Mersenne {
+        let mut limbs = vec![0; p.div_ceil(64) as usize];
+        limbs[0] = v;
+        Mersenne { p, limbs }
+    }
+
+    /// Mask of the bits of the top limb which belong to the residue.
+    fn top_mask(&self) -> u64 {
+        match self.p % 64 {
+            0 => u64::MAX,
+            bits => (1 << bits) - 1,
+        }
+    }
+
+    /// Returns whether the residue is 0, which is also represented as 2^p - 1.
+    fn is_zero(&self) -> bool {
+        let n = self.limbs.len();
+        let ones = self.limbs[..n - 1].iter().all(|x| *x == u64::MAX)
+            && self.limbs[n - 1] == self.top_mask();
+        ones || self.limbs.iter().all(|x| *x == 0)
+    }
+
+    /// Squares the residue, reducing with 2^p = 1 (mod 2^p - 1).
+    fn square(&mut self) {
+        let n = self.limbs.len();
+        let mut product = vec![0u64; 2 * n + 1];
+        for i in 0..n {
+            let mut carry = 0u128;
+            for j in 0..n {
+                let t =
+                    self.limbs[i] as u128 * self.limbs[j] as u128 + product[i + j] as u128 + carry;
+                product[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            product[i + n] = carry as u64;
+        }
+
+        // Add the bits above p onto the bits below it.
+        let (words, bits) = ((self.p / 64) as usize, (self.p % 64) as u32);
+        let mut carry = 0u128;
+        for i in 0..n {
+            let mut high = product[words + i] >> bits;
+            if bits > 0 {
+                high |= product[words + i + 1] << (64 - bits);
+            }
+            let low = if i == n - 1 {
+                product[i] & self.top_mask()
+            } else {
+                product[i]
+            };
+            let t = low as u128 + high as u128 + carry;
+            self.limbs[i] = t as u64;
+            carry = t >> 64;
+        }
+        // The sum is below 2^(p + 1), so one more fold of bit p is enough.
+        let overflow = if bits == 0 {
+            carry as u64
+        } else {
+            let overflow = self.limbs[n - 1] >> bits;
+            self.limbs[n - 1] &= self.top_mask();
+            overflow
+        };
+        if overflow > 0 {
+            self.add_one();
+        }
+    }
+
+    fn add_one(&mut self) {
+        for limb in &mut self.limbs {
+            let (v, carry) = limb.overflowing_add(1);
+            *limb = v;
+            if !carry {
+                break;
+            }
+        }
+    }
+
+    /// Subtracts 2, wrapping around 2^p - 1 below 0.
+    fn sub_two(&mut self) {
+        if self.limbs[0] < 2 && self.limbs[1..].iter().all(|x| *x == 0) {
+            // v - 2 + 2^p - 1, where v is 0 or 1.
+            let v = self.limbs[0];
+            let top = self.top_mask();
+            self.limbs.fill(u64::MAX);
+            *self.limbs.last_mut().unwrap() = top;
+            self.limbs[0] -= 2 - v;
+            return;
+        }
+        let mut borrow = 2;
+        for limb in &mut self.limbs {
+            let (v, under) = limb.overflowing_sub(borrow);
+            *limb = v;
+            if !under {
+                break;
+            }
+            borrow = 1;
+        }
+    }
+}
+
+/// Searches `[start, end]` for the primes belonging to `family` and calls `f`
+/// with them in ascending order. The primes are tested for membership on the
+/// search threads, a chunk at a time, and `searched` is called with the last
+/// number of each chunk once its primes have been passed to `f`.
+pub fn find_family_in_range<F, G>(
+    start: u64,
+    end: u64,
+    options: &SearchOptions,
+    family: Family,
+    mut f: F,
+    mut searched: G,
+) where
+    F: FnMut(u64),
+    G: FnMut(u64),
+{
+    if start > end {
+        return;
+    }
+    let searcher = ChunkSearcher::new(start, end, options.method);
+    let search = |lo, hi| {
+        let mut primes = searcher.primes(lo, hi);
+        primes.retain(|p| family.contains(*p));
+        primes
+    };
+    let chunk_size = options.chunk_size.max(1);
+    search::map_chunks_in_range(start, end, options, search, |chunk, primes| {
+        primes.into_iter().for_each(&mut f);
+        let last = (chunk + 1).saturating_mul(chunk_size) - 1;
+        searched(start.saturating_add(last).min(end));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exponents of the Mersenne primes up to 2^1279 - 1.
+    const MERSENNE_EXPONENTS: [u64; 15] =
+        [2, 3, 5, 7, 13, 17, 19, 31, 61, 89, 107, 127, 521, 607, 1279];
+
+    fn family_in(family: Family, start: u64, end: u64) -> Vec<u64> {
+        let options = SearchOptions {
+            chunk_size: 100,
+            ..SearchOptions::new(2)
+        };
+        let mut primes = Vec::new();
+        find_family_in_range(start, end, &options, family, |p| primes.push(p), |_| {});
+        primes
+    }
+
+    #[test]
+    fn lucas_lehmer_across_limbs() {
+        for p in 0..=200 {
+            assert_eq!(lucas_lehmer(p), MERSENNE_EXPONENTS.contains(&p), "{}", p);
+        }
+        for p in [521, 607, 1279] {
+            assert!(lucas_lehmer(p), "{}", p);
+        }
+        // Prime exponents whose Mersenne numbers are composite, some on either
+        // side of the 64, 128, 192 and 1280 bit limb boundaries.
+        for p in [11, 23, 59, 67, 131, 191, 193, 523, 1277, 1283] {
+            assert!(is_prime(p));
+            assert!(!lucas_lehmer(p), "{}", p);
+        }
+    }
+
+    #[test]
+    fn mersenne_search() {
+        let options = SearchOptions {
+            chunk_size: MERSENNE_CHUNK_SIZE,
+            ..SearchOptions::new(2)
+        };
+        let mut exponents = Vec::new();
+        let mersenne = Family::Mersenne;
+        find_family_in_range(0, 130, &options, mersenne, |p| exponents.push(p), |_| {});
+        assert_eq!(exponents, MERSENNE_EXPONENTS[..12]);
+    }
+
+    #[test]
+    fn families_up_to_1000() {
+        let sophie_germain = [
+            2, 3, 5, 11, 23, 29, 41, 53, 83, 89, 113, 131, 173, 179, 191, 233, 239, 251, 281, 293,
+            359, 419, 431, 443, 491, 509, 593, 641, 653, 659, 683, 719, 743, 761, 809, 911, 953,
+        ];
+        assert_eq!(family_in(Family::SophieGermain, 0, 1000), sophie_germain);
+        let safe: Vec<u64> = sophie_germain.iter().map(|p| 2 * p + 1).collect();
+        let below = safe.iter().filter(|p| **p <= 1000).count();
+        assert_eq!(family_in(Family::Safe, 0, 1000), safe[..below]);
+        let palindromic = [
+            2, 3, 5, 7, 11, 101, 131, 151, 181, 191, 313, 353, 373, 383, 727, 757, 787, 797, 919,
+            929,
+        ];
+        assert_eq!(family_in(Family::Palindromic, 0, 1000), palindromic);
+        let form = Family::Form(Form { a: 10, b: 7 });
+        assert_eq!(family_in(form, 0, 100), [7, 17, 37, 47, 67, 97]);
+    }
+
+    #[test]
+    fn family_edges() {
+        assert!(!Family::SophieGermain.contains(18446744073709551557));
+        assert!(!Family::Safe.contains(2));
+        assert!(Family::Safe.contains(5));
+        assert!(Family::Palindromic.contains(2));
+        assert!(!Family::Palindromic.contains(13));
+        let a = Form { a: 0, b: 5 };
+        assert!(a.contains(5) && !a.contains(10));
+        let b = Form { a: 4, b: 3 };
+        assert!(b.contains(3) && b.contains(7) && !b.contains(5) && !b.contains(1));
+    }
+
+    #[test]
+    fn parse_forms() {
+        let form = |a, b| Ok(Form { a, b });
+        assert_eq!("4*n+3".parse(), form(4, 3));
+        assert_eq!("4n+3".parse(), form(4, 3));
+        assert_eq!("6*n".parse(), form(6, 0));
+        assert_eq!("n+1".parse(), form(1, 1));
+        assert_eq!("n".parse(), form(1, 0));
+        for s in ["", "*n+1", "4*m+3", "4*n+", "x*n", "4*n+3+1"] {
+            assert_eq!(s.parse::<Form>(), Err(()), "{}", s);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::primality::is_prime;
+use crate::primes::Primes;
+use crate::search::{self, SearchOptions};
+use crate::sieve::{self, SegmentedSieve};
+
+/// Smallest primes p tried against a bitmap of the primes just below each
+/// chunk. The minimal p of every even number checked so far is far smaller.
+const WINDOW: u64 = 1 << 16;
+
+/// Default amount of the number line handed to a thread at a time when
+/// verifying a range, since every even number in the chunk produces output.
+pub const DEFAULT_CHUNK_SIZE: u64 = 1 << 20;
+
+/// The primes in `[lo, hi]` as one bit per number.
+struct PrimeBitmap {
+    lo: u64,
+    bits: Vec<u64>,
+}
+
+impl PrimeBitmap {
+    fn new(lo: u64, hi: u64, base: &[u32]) -> PrimeBitmap {
+        let mut bits = vec![0u64; ((hi - lo) / 64 + 1) as usize];
+        SegmentedSieve::new(base).for_each_prime(lo, hi, |p| {
+            let i = p - lo;
+            bits[(i / 64) as usize] |= 1 << (i % 64);
+        });
+        PrimeBitmap { lo, bits }
+    }
+
+    /// Returns whether `v`, which must be within the bitmap, is prime.
+    fn contains(&self, v: u64) -> bool {
+        let i = v - self.lo;
+        self.bits[(i / 64) as usize] & (1 << (i % 64)) != 0
+    }
+}
+
+/// Returns the smallest prime p such that `n - p` is also prime, for the even
+/// `n` at least 4, or `None` if `n` is a counterexample. `n - p` is looked up
+/// in `bitmap` for the primes in `small`, and tested directly beyond them.
+fn minimal_decomposition(n: u64, small: &[u32], bitmap: &PrimeBitmap) -> Option<u64> {
+    for &p in small {
+        let p = p as u64;
+        if p > n / 2 {
+            return None;
+        }
+        if bitmap.contains(n - p) {
+            return Some(p);
+        }
+    }
+    Primes::new(WINDOW + 1, n / 2).find(|p| is_prime(n - p))
+}
+
+/// Checks Goldbach's conjecture for every even number in `[start, end]` from 4
+/// on, calling `f` in ascending order with each number and the smallest prime
+/// p for which `n - p` is also prime, or `None` for a counterexample.
+///
+/// Each chunk is checked against a bitmap of the primes from `WINDOW` below
+/// the chunk to its end, sieved once per chunk on the search threads.
+pub fn verify_range<F>(start: u64, end: u64, options: &SearchOptions, mut f: F)
+where
+    F: FnMut(u64, Option<u64>),
+{
+    let start = start.max(4);
+    if start > end {
+        return;
+    }
+    let small = sieve::base_primes(WINDOW as u32);
+    let base = sieve::base_primes(sieve::isqrt(end) as u32);
+    let verify = |lo: u64, hi: u64| {
+        let bitmap = PrimeBitmap::new(lo.saturating_sub(WINDOW), hi, &base);
+        (lo..=hi)
+            .filter(|n| n.is_multiple_of(2))
+            .map(|n| minimal_decomposition(n, &small, &bitmap))
+            .collect::<Vec<_>>()
+    };
+    search::map_chunks_in_range(start, end, options, verify, |chunk, decompositions| {
+        let lo = start + chunk * options.chunk_size.max(1);
+        let evens = (lo..=end).filter(|n| n.is_multiple_of(2));
+        for (n, p) in evens.zip(decompositions) {
+            f(n, p);
+        }
+    });
+}
+
+/// Summary of a verified range.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    /// Number of even numbers checked.
+    pub checked: u64,
+    /// The even number with the largest minimal p, and that p.
+    pub largest: Option<(u64, u64)>,
+    /// Even numbers which aren't the sum of two primes.
+    pub counterexamples: Vec<u64>,
+}
+
+impl Summary {
+    /// Adds the result for `n` as passed by `verify_range`.
+    pub fn add(&mut self, n: u64, p: Option<u64>) {
+        self.checked += 1;
+        match p {
+            Some(p) if self.largest.is_none_or(|(_, largest)| p > largest) => {
+                self.largest = Some((n, p))
+            }
+            Some(_) => {}
+            None => self.counterexamples.push(n),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+pub mod bench;
+pub mod checkpoint;
+pub mod distributed;
+pub mod factor;
+pub mod families;
+pub mod goldbach;
+pub mod output;
+pub mod primality;
+pub mod primes;
+pub mod search;
+pub mod server;
+pub mod sieve;
+pub mod stats;
+pub mod table;
+pub mod verify;
+
+pub use factor::factor;
+pub use primality::is_prime;
+pub use primes::{next_prime, nth_prime, prev_prime, Primes};
+pub use search::{find_primes_in_range, Method, SearchOptions};
@@ -1,66 +1,456 @@
-use std::cmp::Reverse;
-use std::collections::BinaryHeap;
+mod cli;
+mod progress;
+
+use std::cell::RefCell;
 use std::env;
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, RecvError, Sender};
-use std::thread;
-
-fn is_prime(v: u32) -> bool {
-    if v > 1 {
-        let v_sqrt = (v as f64).sqrt() as u32 + 1;
-        !(2..v_sqrt).any(|x| v % x == 0)
-    } else {
-        false
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::process;
+use std::time::Instant;
+
+use netprog_p1::bench;
+use netprog_p1::distributed;
+use netprog_p1::factor;
+use netprog_p1::families::{self, Family};
+use netprog_p1::goldbach;
+use netprog_p1::output::{Format, PrimeReader, PrimeWriter};
+use netprog_p1::search::{self, CancelToken, Method};
+use netprog_p1::server;
+use netprog_p1::table::PrimeTable;
+use netprog_p1::verify;
+use netprog_p1::{checkpoint, stats};
+
+use cli::{Command, Config};
+use progress::Progress;
+
+/// Prints the primes stored in a binary prime file, one per line.
+fn decode(path: &str) -> io::Result<()> {
+    let reader = PrimeReader::new(File::open(path)?)?;
+    eprintln!("Primes in range [{}, {}]:", reader.start, reader.end);
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for p in reader {
+        writeln!(out, "{}", p?)?;
     }
+    out.flush()
 }
 
-fn find_primes(start: u32, end: u32, step: u32, tx: Sender<u32>) {
-    (start..=end)
-        .step_by(step as usize)
-        .filter(|v| is_prime(*v))
-        .for_each(|v| tx.send(v).unwrap());
+/// Keeps the first error of a sequence of writes made from a callback which
+/// can't return it.
+fn keep_first(error: &mut io::Result<()>, result: io::Result<()>) {
+    if error.is_ok() {
+        *error = result;
+    }
 }
 
-fn find_primes_in_range(start: u32, end: u32, threads: u32) -> BinaryHeap<Reverse<u32>> {
-    let (tx, rx): (Sender<u32>, Receiver<u32>) = mpsc::channel();
-    let handles: Vec<thread::JoinHandle<()>> = (0..threads)
-        .map(|x| (x, tx.clone()))
-        .map(|x| thread::spawn(move || find_primes(start + x.0, end, threads, x.1)))
-        .collect();
-    drop(tx);
-    let mut heap = BinaryHeap::new();
-
-    loop {
-        let result = rx.recv();
-        if result == Err(RecvError) {
-            break;
-        }
-        heap.push(Reverse(result.unwrap()));
+/// Prints the prime factors of every number in the range, one number per line.
+fn factor_range(config: &Config) -> io::Result<()> {
+    let mut out = open_output(config)?;
+    let mut result = Ok(());
+    let mut progress = Progress::new(config.start, config.end, "numbers", config.progress);
+    factor::factor_range(config.start, config.end, &config.options, |n, factors| {
+        progress.update(n);
+        let mut line = format!("{}:", n);
+        for p in factors {
+            line += &format!(" {}", p);
+        }
+        keep_first(&mut result, writeln!(out, "{}", line));
+    });
+    progress.finish();
+    result?;
+    out.flush()
+}
+
+/// Prints every prime of `family` in the range in the chosen format.
+fn list_family(config: &Config, family: Family) -> io::Result<()> {
+    let out = open_output(config)?;
+    let started = Instant::now();
+    let (start, end) = (config.start, config.end);
+    let mut writer = PrimeWriter::new(config.format, out, start, end, &config.options)?;
+    let mut result = Ok(());
+    let progress = RefCell::new(Progress::new(start, end, "primes", config.progress));
+    families::find_family_in_range(
+        start,
+        end,
+        &config.options,
+        family,
+        |p| {
+            progress.borrow_mut().update(p);
+            keep_first(&mut result, writer.write(p));
+        },
+        |n| progress.borrow_mut().searched(n),
+    );
+    progress.borrow().finish();
+    result?;
+    writer.finish(started.elapsed(), Some(end))
+}
+
+/// Checks Goldbach's conjecture for every even number in the range, printing
+/// each minimal decomposition unless only a summary was asked for.
+fn verify_goldbach(config: &Config) -> io::Result<()> {
+    let mut out = open_output(config)?;
+    let mut result = Ok(());
+    let mut summary = goldbach::Summary::default();
+    let mut progress = Progress::new(config.start, config.end, "even numbers", config.progress);
+    goldbach::verify_range(config.start, config.end, &config.options, |n, p| {
+        progress.update(n);
+        summary.add(n, p);
+        if p.is_none() {
+            eprintln!("Counterexample: {}", n);
+        }
+        if !config.summary {
+            let line = match p {
+                Some(p) => writeln!(out, "{} = {} + {}", n, p, n - p),
+                None => writeln!(out, "{} is not the sum of two primes", n),
+            };
+            keep_first(&mut result, line);
+        }
+    });
+    progress.finish();
+    result?;
+
+    writeln!(out, "Checked: {}", summary.checked)?;
+    if let Some((n, p)) = summary.largest {
+        writeln!(out, "Largest minimal p: {} = {} + {}", n, p, n - p)?;
     }
-    handles.into_iter().for_each(|x| x.join().unwrap());
-    heap
+    writeln!(out, "Counterexamples: {}", summary.counterexamples.len())?;
+    out.flush()
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let start = args[1].parse().unwrap();
-    let end = args[2].parse().unwrap();
-    let threads = args[3].parse().unwrap();
-
-    println!(
-        "Finding prime numbers in range [{}, {}] with {} threads...",
-        start, end, threads
+/// Prints whether `n` is prime, looking it up in the table given with
+/// `--table` if it holds `n`.
+fn is_prime(config: &Config, n: u64) -> io::Result<()> {
+    let prime = match &config.table {
+        Some(path) => {
+            let table = PrimeTable::open(path)?;
+            match n < table.end() {
+                true => {
+                    table.check(n)?;
+                    table.is_prime(n)
+                }
+                false => netprog_p1::is_prime(n),
+            }
+        }
+        None => netprog_p1::is_prime(n),
+    };
+    match prime {
+        true => println!("{} is prime", n),
+        false => println!("{} is not prime", n),
+    }
+    Ok(())
+}
+
+/// Counts the primes in the range with the table at `path`.
+fn count_with_table(config: &Config, path: &str) -> io::Result<()> {
+    let table = PrimeTable::open(path)?;
+    if config.end >= table.end() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the table only holds the primes below {}", table.end()),
+        ));
+    }
+    table.check(config.end)?;
+    let below = match config.start {
+        0 => 0,
+        start => {
+            table.check(start - 1)?;
+            table.count(start - 1)
+        }
+    };
+    let mut out = open_output(config)?;
+    writeln!(out, "Count: {}", table.count(config.end) - below)?;
+    out.flush()
+}
+
+/// Searches the range with the chosen method and a different one, printing
+/// every number they disagree on and the check of pi(10^k) against the known
+/// values. Fails if anything is off.
+fn cross_check(config: &Config) -> io::Result<()> {
+    let (start, end, options) = (config.start, config.end, &config.options);
+    let first = options.method.resolve(start, end);
+    let second = match first {
+        Method::Sieve => Method::MillerRabin,
+        _ => Method::Sieve,
+    };
+    let mut out = open_output(config)?;
+    writeln!(
+        out,
+        "Checking [{}, {}] with {} against {}...",
+        start, end, first, second
+    )?;
+
+    let mut result = Ok(());
+    let progress = RefCell::new(Progress::new(start, end, "disagreements", config.progress));
+    let report = verify::cross_check(
+        start,
+        end,
+        options,
+        second,
+        |n, by_first| {
+            progress.borrow_mut().update(n);
+            let (only, other) = if by_first {
+                (first, second)
+            } else {
+                (second, first)
+            };
+            let line = writeln!(out, "{} is prime by {} but not by {}", n, only, other);
+            keep_first(&mut result, line);
+        },
+        |n| progress.borrow_mut().searched(n),
     );
+    progress.borrow().finish();
+    result?;
 
-    if threads <= 0 {
-        panic!("Cannot use {} threads.", threads)
+    let mut mismatches = 0;
+    for (k, count) in report.powers {
+        let known = verify::PI_POWERS_OF_TEN[k as usize];
+        let verdict = if count == known { "ok" } else { "MISMATCH" };
+        mismatches += (count != known) as u64;
+        writeln!(
+            out,
+            "pi(10^{}) = {}, known {}: {}",
+            k, count, known, verdict
+        )?;
+    }
+    writeln!(out, "Agreed primes: {}", report.agreed)?;
+    writeln!(out, "Disagreements: {}", report.disagreements)?;
+    out.flush()?;
+    if report.disagreements > 0 || mismatches > 0 {
+        return Err(io::Error::other("verification failed"));
     }
+    Ok(())
+}
 
-    let mut primes = find_primes_in_range(start, end, threads);
-    print!("Primes: ");
-    while primes.len() > 0 {
-        let Reverse(p) = primes.pop().unwrap();
-        print!("{} ", p);
+/// Times searches of the range with each method and thread count, printing a
+/// row for each as soon as it has been timed.
+fn bench(config: &Config) -> io::Result<()> {
+    let mut out = open_output(config)?;
+    let csv = config.format == Format::Csv;
+    if csv {
+        writeln!(
+            out,
+            "method,threads,seconds,numbers_per_second,primes,speedup,efficiency"
+        )?;
+    } else {
+        writeln!(
+            out,
+            "{:<7} {:>7} {:>10} {:>12} {:>12} {:>8} {:>10}",
+            "method", "threads", "seconds", "Mnumbers/s", "primes", "speedup", "efficiency"
+        )?;
+    }
+    out.flush()?;
+
+    let mut result = Ok(());
+    bench::sweep(config.start, config.end, &config.bench, |m| {
+        let seconds = m.elapsed.as_secs_f64();
+        let row = if csv {
+            writeln!(
+                out,
+                "{},{},{:.6},{:.0},{},{:.3},{:.3}",
+                m.method,
+                m.threads,
+                seconds,
+                m.throughput,
+                m.primes,
+                m.speedup,
+                m.efficiency()
+            )
+        } else {
+            writeln!(
+                out,
+                "{:<7} {:>7} {:>10.3} {:>12.1} {:>12} {:>8.2} {:>9.0}%",
+                m.method.to_string(),
+                m.threads,
+                seconds,
+                m.throughput / 1e6,
+                m.primes,
+                m.speedup,
+                m.efficiency() * 100.0
+            )
+        };
+        keep_first(&mut result, row.and_then(|_| out.flush()));
+    });
+    result
+}
+
+/// Opens the file given with `--output`, or standard output.
+fn open_output(config: &Config) -> io::Result<io::BufWriter<Box<dyn Write>>> {
+    let out: Box<dyn Write> = match &config.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    Ok(io::BufWriter::new(out))
+}
+
+fn run(config: Config) -> io::Result<()> {
+    let (start, end, options) = (config.start, config.end, config.options);
+    match &config.command {
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Command::Decode(path) => return decode(path),
+        Command::Worker(addr) => {
+            let chunks = distributed::run_worker(addr, options.threads)?;
+            println!("Searched {} chunks.", chunks);
+            return Ok(());
+        }
+        Command::Serve(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            eprintln!("Prime server listening on {}", addr);
+            server::run_server(listener, &config.server);
+            return Ok(());
+        }
+        Command::Bench => return bench(&config),
+        Command::IsPrime(n) => return is_prime(&config, *n),
+        Command::BuildTable(path) => {
+            let table = PrimeTable::build(path, end.saturating_add(1), &options)?;
+            println!("Saved the primes below {} to {}", table.end(), path);
+            return Ok(());
+        }
+        Command::Count if config.table.is_some() => {
+            return count_with_table(&config, config.table.as_deref().unwrap())
+        }
+        _ if config.verify => return cross_check(&config),
+        Command::Factor => return factor_range(&config),
+        Command::Family(family) => return list_family(&config, *family),
+        Command::Goldbach => return verify_goldbach(&config),
+        _ => {}
+    }
+
+    // Keep machine-readable output free of anything but the primes.
+    if config.format == Format::Line {
+        println!(
+            "Finding prime numbers in range [{}, {}] with {} threads ({:?})...",
+            start, end, options.threads, options.method
+        );
+    }
+
+    let listener = match &config.coordinate {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            eprintln!("Coordinator listening on {}", addr);
+            Some(listener)
+        }
+        None => None,
+    };
+    let coordinator_options = config.coordinator_options();
+    let cancel = match config.time_limit {
+        Some(limit) => CancelToken::with_budget(limit),
+        None => CancelToken::new(),
+    };
+    if config.checkpoint.is_none() && listener.is_none() && config.format != Format::Binary {
+        // Stop at the first Ctrl-C and print what was found, but let a second
+        // one end the process right away. Binary files can't tell that they
+        // only hold part of the range, so those are left to the default
+        // handler.
+        let cancel = cancel.clone();
+        let handler = move || {
+            if cancel.is_cancelled() {
+                process::exit(130);
+            }
+            cancel.cancel();
+        };
+        ctrlc::set_handler(handler).map_err(io::Error::other)?;
+    }
+    let mut progress = Progress::new(start, end, "primes", config.progress);
+    let mut search_result = Ok(());
+    let mut searched = Some(end);
+    let run_search = |f: &mut dyn FnMut(u64)| {
+        let f = &mut |p| {
+            progress.update(p);
+            f(p)
+        };
+        search_result = match (&config.checkpoint, listener) {
+            (Some(path), _) => {
+                checkpoint::find_primes_in_range(start, end, &options, path, config.resume, f)
+            }
+            (None, Some(listener)) => {
+                distributed::run_coordinator(listener, start, end, &coordinator_options, f)
+            }
+            (None, None) => {
+                searched = search::find_primes_until(start, end, &options, &cancel, f);
+                Ok(())
+            }
+        };
+        progress.finish();
+    };
+
+    let mut out = open_output(&config)?;
+    let mut result = Ok(());
+    match config.command {
+        Command::List => {
+            let started = Instant::now();
+            let mut writer = PrimeWriter::new(config.format, &mut out, start, end, &options)?;
+            run_search(&mut |p| keep_first(&mut result, writer.write(p)));
+            search_result?;
+            result?;
+            writer.finish(started.elapsed(), searched)?;
+        }
+        Command::Count => {
+            let count = stats::count(run_search);
+            search_result?;
+            writeln!(out, "Count: {}", count)?;
+        }
+        Command::Pairs(distance) => {
+            let count = stats::pairs(run_search, distance, |p, q| {
+                keep_first(&mut result, writeln!(out, "{} {}", p, q));
+            });
+            search_result?;
+            result?;
+            writeln!(out, "Pairs: {}", count)?;
+        }
+        Command::Gaps => {
+            let max = stats::record_gaps(run_search, |gap| {
+                let line = writeln!(out, "{} between {} and {}", gap.size(), gap.lo, gap.hi);
+                keep_first(&mut result, line);
+            });
+            search_result?;
+            result?;
+            match max {
+                Some(gap) => writeln!(out, "Maximal gap: {}", gap.size())?,
+                None => writeln!(out, "Maximal gap: none")?,
+            }
+        }
+        Command::Sum => {
+            let sum = stats::sum(run_search);
+            search_result?;
+            writeln!(out, "Sum: {}", sum)?;
+        }
+        Command::Help
+        | Command::Decode(_)
+        | Command::Worker(_)
+        | Command::Serve(_)
+        | Command::Bench
+        | Command::IsPrime(_)
+        | Command::BuildTable(_)
+        | Command::Factor
+        | Command::Family(_)
+        | Command::Goldbach => unreachable!(),
+    }
+    out.flush()?;
+    match searched {
+        Some(last) if last == end => Ok(()),
+        Some(last) => Err(io::Error::other(format!(
+            "search stopped early; only [{}, {}] was searched",
+            start, last
+        ))),
+        None => Err(io::Error::other(
+            "search stopped before any chunk was finished",
+        )),
+    }
+}
+
+fn main() {
+    let config = match cli::parse_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(config) {
+        eprintln!("error: {}", e);
+        process::exit(1);
     }
-    println!("");
 }
@@ -0,0 +1,282 @@
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::search::SearchOptions;
+
+/// Magic bytes at the start of a binary prime file.
+const BINARY_MAGIC: &[u8; 4] = b"PRMS";
+const BINARY_VERSION: u8 = 1;
+
+/// How listed primes are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `Primes: 2 3 5 ...` on a single line.
+    Line,
+    /// One prime per line.
+    Text,
+    /// A JSON object with the range, search options, primes and timing.
+    Json,
+    /// CSV with a header row and one `index,prime` row per prime.
+    Csv,
+    /// A header followed by the gaps between consecutive primes as LEB128
+    /// varints. Read back with `PrimeReader`.
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line" => Ok(Format::Line),
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!(
+                "unknown format '{}' (expected line, text, json, csv or binary)",
+                s
+            )),
+        }
+    }
+}
+
+/// Writes a stream of ascending primes in one of the supported formats.
+pub struct PrimeWriter<W: Write> {
+    format: Format,
+    out: W,
+    count: u64,
+    previous: u64,
+}
+
+impl<W: Write> PrimeWriter<W> {
+    /// Creates a writer and writes the header of the format, describing the
+    /// search of `[start, end]` with `options` and the method it resolves to.
+    pub fn new(
+        format: Format,
+        mut out: W,
+        start: u64,
+        end: u64,
+        options: &SearchOptions,
+    ) -> io::Result<PrimeWriter<W>> {
+        match format {
+            Format::Line => write!(out, "Primes: ")?,
+            Format::Text => {}
+            Format::Json => write!(
+                out,
+                "{{\"start\":{},\"end\":{},\"threads\":{},\"method\":\"{}\",\"chunk_size\":{},\"primes\":[",
+                start,
+                end,
+                options.threads,
+                options.method.resolve(start, end),
+                options.chunk_size
+            )?,
+            Format::Csv => writeln!(out, "index,prime")?,
+            Format::Binary => {
+                out.write_all(BINARY_MAGIC)?;
+                out.write_all(&[BINARY_VERSION])?;
+                out.write_all(&start.to_le_bytes())?;
+                out.write_all(&end.to_le_bytes())?;
+            }
+        }
+        Ok(PrimeWriter {
+            format,
+            out,
+            count: 0,
+            previous: start,
+        })
+    }
+
+    /// Writes the next prime, which must not be smaller than the previous one.
+    pub fn write(&mut self, p: u64) -> io::Result<()> {
+        match self.format {
+            Format::Line => write!(self.out, "{} ", p)?,
+            Format::Text => writeln!(self.out, "{}", p)?,
+            Format::Json if self.count == 0 => write!(self.out, "{}", p)?,
+            Format::Json => write!(self.out, ",{}", p)?,
+            Format::Csv => writeln!(self.out, "{},{}", self.count + 1, p)?,
+            Format::Binary => write_varint(&mut self.out, p - self.previous)?,
+        }
+        self.count += 1;
+        self.previous = p;
+        Ok(())
+    }
+
+    /// Writes the trailer of the format and flushes the output. `elapsed` is
+    /// the time the search took, and `searched` the last number searched if
+    /// the search stopped before the end, or `None` if it stopped before
+    /// searching anything. Binary files can't record that, so only complete
+    /// searches should be written in `Format::Binary`.
+    pub fn finish(mut self, elapsed: Duration, searched: Option<u64>) -> io::Result<()> {
+        match self.format {
+            Format::Line => writeln!(self.out)?,
+            Format::Json => writeln!(
+                self.out,
+                "],\"count\":{},\"searched_end\":{},\"elapsed_seconds\":{}}}",
+                self.count,
+                searched.map_or("null".to_string(), |x| x.to_string()),
+                elapsed.as_secs_f64()
+            )?,
+            _ => {}
+        }
+        self.out.flush()
+    }
+}
+
+/// Writes `v` as an unsigned LEB128 varint.
+pub fn write_varint<W: Write>(out: &mut W, mut v: u64) -> io::Result<()> {
+    let mut buf = [0; 10];
+    let mut len = 0;
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    out.write_all(&buf[..len])
+}
+
+/// Reads an unsigned LEB128 varint from `input`, returning `None` if the input
+/// ends before the first byte.
+pub fn read_varint<I>(input: &mut I) -> io::Result<Option<u64>>
+where
+    I: Iterator<Item = io::Result<u8>>,
+{
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = match input.next() {
+            Some(byte) => byte?,
+            None if shift == 0 => return Ok(None),
+            None => return Err(ErrorKind::UnexpectedEof.into()),
+        };
+        v |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(Some(v));
+        }
+    }
+    Err(io::Error::new(ErrorKind::InvalidData, "varint too long"))
+}
+
+/// Reads the primes of a file written in `Format::Binary`.
+pub struct PrimeReader<R: Read> {
+    input: io::Bytes<BufReader<R>>,
+    previous: u64,
+    /// Range which was searched to produce the file.
+    pub start: u64,
+    pub end: u64,
+}
+
+impl<R: Read> PrimeReader<R> {
+    /// Reads and validates the header.
+    pub fn new(input: R) -> io::Result<PrimeReader<R>> {
+        let mut input = BufReader::new(input);
+        let mut header = [0; 21];
+        input.read_exact(&mut header)?;
+        if &header[..4] != BINARY_MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a binary prime file",
+            ));
+        }
+        if header[4] != BINARY_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported binary prime file version {}", header[4]),
+            ));
+        }
+        let start = u64::from_le_bytes(header[5..13].try_into().unwrap());
+        let end = u64::from_le_bytes(header[13..21].try_into().unwrap());
+        Ok(PrimeReader {
+            input: input.bytes(),
+            previous: start,
+            start,
+            end,
+        })
+    }
+}
+
+impl<R: Read> Iterator for PrimeReader<R> {
+    type Item = io::Result<u64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match read_varint(&mut self.input) {
+            Ok(Some(delta)) => match self.previous.checked_add(delta) {
+                Some(p) => {
+                    self.previous = p;
+                    Some(Ok(p))
+                }
+                None => Some(Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "prime out of range",
+                ))),
+            },
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Method;
+
+    #[test]
+    fn binary_round_trip() {
+        // Gaps above 2^7, 2^14 and 2^21, and the widest possible.
+        let primes = [2, 3, 3 + 200, 3 + 200 + 20_000, 5_000_000, u64::MAX];
+        let mut out = Vec::new();
+        let options = SearchOptions::new(1);
+        let mut writer = PrimeWriter::new(Format::Binary, &mut out, 0, u64::MAX, &options).unwrap();
+        for p in primes {
+            writer.write(p).unwrap();
+        }
+        writer.finish(Duration::ZERO, Some(u64::MAX)).unwrap();
+
+        let reader = PrimeReader::new(&out[..]).unwrap();
+        assert_eq!((reader.start, reader.end), (0, u64::MAX));
+        let read: Vec<u64> = reader.map(|p| p.unwrap()).collect();
+        assert_eq!(read, primes);
+
+        let truncated = PrimeReader::new(&out[..out.len() - 1]).unwrap();
+        assert!(truncated.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn varint_lengths() {
+        for (v, len) in [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (16383, 2),
+            (16384, 3),
+            (u64::MAX, 10),
+        ] {
+            let mut out = Vec::new();
+            write_varint(&mut out, v).unwrap();
+            assert_eq!(out.len(), len, "{}", v);
+            let mut input = out.into_iter().map(Ok);
+            assert_eq!(read_varint(&mut input).unwrap(), Some(v));
+            assert!(input.next().is_none());
+        }
+    }
+
+    #[test]
+    fn json_header_has_resolved_method() {
+        let mut out = Vec::new();
+        let options = SearchOptions {
+            method: Method::Auto,
+            ..SearchOptions::new(1)
+        };
+        let writer = PrimeWriter::new(Format::Json, &mut out, 0, 100, &options).unwrap();
+        writer.finish(Duration::ZERO, Some(100)).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains("\"method\":\"sieve\""), "{}", json);
+    }
+}
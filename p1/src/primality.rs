@@ -0,0 +1,125 @@
+use crate::sieve::isqrt;
+
+/// Small primes used both to reject most composites cheaply before running
+/// Miller-Rabin and as its witnesses. Testing against every prime up to 37 is
+/// deterministic for all 64-bit inputs.
+const SMALL_PRIMES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+/// Tests `v` by trial division with every integer up to its square root.
+pub fn trial_division(v: u64) -> bool {
+    if v > 1 {
+        let v_sqrt = isqrt(v) + 1;
+        !(2..v_sqrt).any(|x| v.is_multiple_of(x))
+    } else {
+        false
+    }
+}
+
+/// Tests `v` with a deterministic Miller-Rabin test, correct for every `u64`.
+pub fn is_prime(v: u64) -> bool {
+    if v < 2 {
+        return false;
+    }
+    for p in SMALL_PRIMES {
+        if v == p {
+            return true;
+        }
+        if v.is_multiple_of(p) {
+            return false;
+        }
+    }
+    if v < 41 * 41 {
+        return true;
+    }
+    miller_rabin(v)
+}
+
+/// Runs Miller-Rabin on an odd `n > 37` with every witness in `SMALL_PRIMES`.
+fn miller_rabin(n: u64) -> bool {
+    let d = (n - 1) >> (n - 1).trailing_zeros();
+    let s = (n - 1).trailing_zeros();
+
+    'witness: for a in SMALL_PRIMES {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+/// Computes `a * b mod m` without overflowing.
+pub fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    (a as u128 * b as u128 % m as u128) as u64
+}
+
+/// Computes `base ^ exp mod m` by repeated squaring.
+pub fn pow_mod(base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut result = 1 % m;
+    let mut base = base % m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agrees_with_trial_division() {
+        for v in 0..5000 {
+            assert_eq!(is_prime(v), trial_division(v), "{}", v);
+        }
+        let v = 1_000_000_007;
+        assert!((v - 200..v + 200).all(|v| is_prime(v) == trial_division(v)));
+    }
+
+    #[test]
+    fn rejects_strong_pseudoprimes() {
+        // Each fools Miller-Rabin with some prefix of the witnesses.
+        for v in [
+            2047,
+            1373653,
+            25326001,
+            3215031751,
+            2152302898747,
+            3474749660383,
+            341550071728321,
+            3825123056546413051,
+        ] {
+            assert!(!is_prime(v), "{}", v);
+        }
+    }
+
+    #[test]
+    fn near_u64_max() {
+        assert!(is_prime(18446744073709551557));
+        assert!(is_prime(4294967291));
+        assert!(!is_prime(4294967291 * 4294967291));
+        assert!(!is_prime(u64::MAX));
+        assert!(!is_prime(u64::MAX - 1));
+        let last = (18446744073709551557..=u64::MAX).filter(|v| is_prime(*v));
+        assert_eq!(last.count(), 1);
+    }
+
+    #[test]
+    fn modular_arithmetic_doesnt_overflow() {
+        let m = u64::MAX;
+        assert_eq!(mul_mod(m - 1, m - 1, m), 1);
+        assert_eq!(pow_mod(2, 64, m), 1);
+        assert_eq!(pow_mod(5, 0, 1), 0);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::primality::is_prime;
+use crate::search::Method;
+use crate::sieve::{self, SegmentedSieve};
+
+/// Amount of the number line tested at a time when `Primes` is far enough from
+/// its start that sieving wouldn't pay off yet. Kept small so that the first
+/// primes of a sparse range are produced quickly.
+const SPARSE_SPAN: u64 = 1024;
+
+/// Iterator over the primes in `[start, end]`, in ascending order.
+///
+/// Primes are produced one segment at a time on the calling thread. Segments
+/// are sieved once enough of the range has been covered to pay for the base
+/// primes, which are extended as the iterator advances, and tested with
+/// Miller-Rabin before that.
+pub struct Primes {
+    start: u64,
+    end: u64,
+    /// Start of the next segment, or `None` once the range is exhausted.
+    next: Option<u64>,
+    base: Vec<u32>,
+    buffer: Vec<u64>,
+    pos: usize,
+}
+
+impl Primes {
+    pub fn new(start: u64, end: u64) -> Primes {
+        Primes {
+            start,
+            end,
+            next: Some(start).filter(|x| *x <= end),
+            base: Vec::new(),
+            buffer: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Fills the buffer with the primes of the next segment.
+    fn fill(&mut self, lo: u64) {
+        self.buffer.clear();
+        self.pos = 0;
+
+        let mut hi = self.end.min(lo.saturating_add(sieve::SEGMENT_SPAN - 1));
+        if Method::Auto.resolve(self.start, hi) == Method::Sieve {
+            let limit = sieve::isqrt(hi);
+            if self.base.last().is_none_or(|x| (*x as u64) < limit) {
+                // Overshoot so the base primes aren't recomputed every segment.
+                let limit = limit.saturating_mul(2).min(u32::MAX as u64);
+                self.base = sieve::base_primes(limit as u32);
+            }
+            let buffer = &mut self.buffer;
+            SegmentedSieve::new(&self.base).for_each_prime(lo, hi, |p| buffer.push(p));
+        } else {
+            hi = self.end.min(lo.saturating_add(SPARSE_SPAN - 1));
+            self.buffer.extend((lo..=hi).filter(|v| is_prime(*v)));
+        }
+        self.next = hi.checked_add(1).filter(|x| *x <= self.end);
+    }
+}
+
+impl Iterator for Primes {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        while self.pos == self.buffer.len() {
+            let lo = self.next?;
+            self.fill(lo);
+        }
+        self.pos += 1;
+        Some(self.buffer[self.pos - 1])
+    }
+}
+
+/// Returns the smallest prime greater than `n`, or `None` if there is none
+/// which fits in a `u64`.
+pub fn next_prime(n: u64) -> Option<u64> {
+    let mut candidate = n.checked_add(1)?;
+    while !is_prime(candidate) {
+        candidate = candidate.checked_add(1)?;
+    }
+    Some(candidate)
+}
+
+/// Returns the largest prime smaller than `n`, or `None` if `n <= 2`.
+pub fn prev_prime(n: u64) -> Option<u64> {
+    let mut candidate = n.checked_sub(1)?;
+    while !is_prime(candidate) {
+        candidate = candidate.checked_sub(1)?;
+    }
+    Some(candidate)
+}
+
+/// Returns the `n`th prime, counting 2 as the first, or `None` if `n` is zero
+/// or the prime doesn't fit in a `u64`.
+pub fn nth_prime(n: u64) -> Option<u64> {
+    Primes::new(0, u64::MAX).nth(n.checked_sub(1)? as usize)
+}
@@ -0,0 +1,84 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Number of results between checks of the clock, so that reporting progress
+/// doesn't slow down searches producing millions of results per second.
+const CHECK_EVERY: u64 = 4096;
+
+/// Minimum time between two redraws of the progress line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Live progress line on standard error for a search of `[start, end]`,
+/// estimated from how far into the range the search has got.
+pub struct Progress {
+    start: u64,
+    end: u64,
+    /// What the search finds, such as "primes".
+    unit: &'static str,
+    enabled: bool,
+    started: Instant,
+    drawn: Instant,
+    found: u64,
+}
+
+impl Progress {
+    pub fn new(start: u64, end: u64, unit: &'static str, enabled: bool) -> Progress {
+        let now = Instant::now();
+        Progress {
+            start,
+            end,
+            unit,
+            enabled,
+            started: now,
+            drawn: now,
+            found: 0,
+        }
+    }
+
+    /// Records that `n` has been found, redrawing the line every now and then.
+    pub fn update(&mut self, n: u64) {
+        self.found += 1;
+        if self.found.is_multiple_of(CHECK_EVERY) {
+            self.searched(n);
+        }
+    }
+
+    /// Records that the search has got up to `n`, whether or not anything was
+    /// found, redrawing the line if it hasn't been for a while. For searches
+    /// which find little, called once per chunk.
+    pub fn searched(&mut self, n: u64) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        if now - self.drawn < REDRAW_INTERVAL {
+            return;
+        }
+        self.drawn = now;
+
+        let done = (n - self.start) as f64 / ((self.end - self.start) as f64 + 1.0);
+        let elapsed = (now - self.started).as_secs_f64();
+        let eta = if done > 0.0 {
+            format!("{:.0}s", elapsed * (1.0 - done) / done)
+        } else {
+            "?".to_string()
+        };
+        eprint!(
+            "\r{:5.1}% at {}, {} {}, {:.0}s elapsed, ETA {}  ",
+            done * 100.0,
+            n,
+            self.found,
+            self.unit,
+            elapsed,
+            eta
+        );
+        _ = io::stderr().flush();
+    }
+
+    /// Clears the progress line.
+    pub fn finish(&self) {
+        if self.enabled {
+            eprint!("\r{:80}\r", "");
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::primality;
+use crate::sieve::{self, SegmentedSieve};
+
+/// Ranges at least this many times narrower than the square root of their end
+/// are tested with Miller-Rabin by `Method::Auto`, since producing the base
+/// primes for the sieve would cost more than testing each number directly.
+const SPARSE_RATIO: u64 = 16;
+
+/// Default amount of the number line handed to a thread at a time.
+pub const DEFAULT_CHUNK_SIZE: u64 = sieve::SEGMENT_SPAN * 8;
+
+/// Number of chunks per thread which may be in flight (being searched or
+/// waiting to be emitted) at once. Bounds the memory used by the search.
+const CHUNKS_IN_FLIGHT: u64 = 2;
+
+/// Algorithm used to find the primes in a range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Picks `Sieve` or `MillerRabin` depending on the width of the range.
+    Auto,
+    /// Segmented sieve of Eratosthenes.
+    Sieve,
+    /// Deterministic Miller-Rabin test of every odd number.
+    MillerRabin,
+    /// Trial division of every number, kept around for comparison.
+    TrialDivision,
+}
+
+impl Method {
+    /// Resolves `Auto` to the concrete method best suited for `[start, end]`.
+    pub fn resolve(self, start: u64, end: u64) -> Method {
+        match self {
+            Method::Auto if end < start || end - start >= sieve::isqrt(end) / SPARSE_RATIO => {
+                Method::Sieve
+            }
+            Method::Auto => Method::MillerRabin,
+            method => method,
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Method::Auto),
+            "sieve" => Ok(Method::Sieve),
+            "mr" | "miller-rabin" => Ok(Method::MillerRabin),
+            "trial" => Ok(Method::TrialDivision),
+            _ => Err(format!(
+                "unknown method '{}' (expected auto, sieve, mr or trial)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Method::Auto => "auto",
+            Method::Sieve => "sieve",
+            Method::MillerRabin => "mr",
+            Method::TrialDivision => "trial",
+        })
+    }
+}
+
+/// Finds the primes in chunks of a range, using the method resolved for the
+/// whole range and sharing its base primes between the search threads.
+pub struct ChunkSearcher {
+    method: Method,
+    base: Vec<u32>,
+}
+
+impl ChunkSearcher {
+    /// Prepares to search chunks of `[start, end]` with `method`.
+    pub fn new(start: u64, end: u64, method: Method) -> ChunkSearcher {
+        let method = method.resolve(start, end);
+        let base = match method {
+            Method::Sieve => sieve::base_primes(sieve::isqrt(end) as u32),
+            _ => Vec::new(),
+        };
+        ChunkSearcher { method, base }
+    }
+
+    /// Returns the primes in `[lo, hi]` in ascending order.
+    pub fn primes(&self, lo: u64, hi: u64) -> Vec<u64> {
+        let mut primes = Vec::new();
+        match self.method {
+            Method::Sieve => {
+                SegmentedSieve::new(&self.base).for_each_prime(lo, hi, |p| primes.push(p))
+            }
+            Method::TrialDivision => {
+                primes.extend((lo..=hi).filter(|v| primality::trial_division(*v)))
+            }
+            _ => primes.extend((lo..=hi).filter(|v| primality::is_prime(*v))),
+        }
+        primes
+    }
+}
+
+/// Tunables of a range search.
+#[derive(Clone, Copy, Debug)]
+pub struct SearchOptions {
+    /// Number of threads searching chunks. Zero is treated as one.
+    pub threads: u64,
+    pub method: Method,
+    /// Amount of the number line handed to a thread at a time. Zero is treated
+    /// as one.
+    pub chunk_size: u64,
+}
+
+impl SearchOptions {
+    pub fn new(threads: u64) -> SearchOptions {
+        SearchOptions {
+            threads,
+            method: Method::Auto,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// Flag to stop a search early, either explicitly or once its time budget is
+/// spent. Clones share the flag, so one can be handed to another thread or a
+/// signal handler to cancel the search.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Returns a token which also cancels itself once `budget` has passed.
+    pub fn with_budget(budget: Duration) -> CancelToken {
+        CancelToken {
+            deadline: Instant::now().checked_add(budget),
+            ..CancelToken::default()
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.deadline.is_some_and(|x| Instant::now() >= x)
+    }
+}
+
+struct QueueState {
+    /// Index of the next chunk to hand out.
+    next: u64,
+    /// Number of chunks which have been emitted, in order.
+    emitted: u64,
+}
+
+/// Shared queue of chunks. Threads take the lowest chunk not yet taken, so
+/// every thread stays busy until the range is exhausted, but never run further
+/// ahead of the ordered emission than `limit` chunks.
+struct WorkQueue {
+    chunks: u64,
+    limit: u64,
+    state: Mutex<QueueState>,
+    cvar: Condvar,
+}
+
+impl WorkQueue {
+    fn new(chunks: u64, limit: u64) -> WorkQueue {
+        WorkQueue {
+            chunks,
+            limit,
+            state: Mutex::new(QueueState {
+                next: 0,
+                emitted: 0,
+            }),
+            cvar: Condvar::new(),
+        }
+    }
+
+    /// Takes the next chunk, blocking while too many chunks are in flight.
+    /// Returns `None` once every chunk has been taken or `cancel` has been
+    /// cancelled.
+    fn take(&self, cancel: &CancelToken) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        while state.next < self.chunks && state.next >= state.emitted + self.limit {
+            state = self.cvar.wait(state).unwrap();
+        }
+        if state.next == self.chunks || cancel.is_cancelled() {
+            return None;
+        }
+        state.next += 1;
+        Some(state.next - 1)
+    }
+
+    /// Records that the first `emitted` chunks have been emitted.
+    fn set_emitted(&self, emitted: u64) {
+        self.state.lock().unwrap().emitted = emitted;
+        self.cvar.notify_all();
+    }
+}
+
+/// Searches `[start, end]` and calls `f` with every prime in ascending order.
+///
+/// The range is split into contiguous chunks of `options.chunk_size` numbers
+/// which the threads take from a shared queue. Finished chunks are emitted as
+/// soon as every chunk before them has been emitted, so primes are streamed out
+/// while the search is still running and only the chunks in flight are ever
+/// held in memory.
+pub fn find_primes_in_range<F>(start: u64, end: u64, options: &SearchOptions, mut f: F)
+where
+    F: FnMut(u64),
+{
+    find_chunks_in_range(start, end, options, |_, primes| {
+        primes.into_iter().for_each(&mut f)
+    });
+}
+
+/// Like `find_primes_in_range`, but stops early once `cancel` is cancelled.
+/// Returns the last number of the part of the range which was searched,
+/// `[start, returned]`, which is `end` unless the search was cancelled, or
+/// `None` if it was cancelled before any chunk was finished.
+///
+/// Cancellation stops the threads from taking new chunks, but the chunks they
+/// are working on are finished and emitted, so every prime in the searched
+/// part is passed to `f`.
+pub fn find_primes_until<F>(
+    start: u64,
+    end: u64,
+    options: &SearchOptions,
+    cancel: &CancelToken,
+    mut f: F,
+) -> Option<u64>
+where
+    F: FnMut(u64),
+{
+    if start > end {
+        return None;
+    }
+    let searcher = ChunkSearcher::new(start, end, options.method);
+    let search = |lo, hi| searcher.primes(lo, hi);
+    let chunks = map_chunks_until(start, end, options, cancel, search, |_, primes| {
+        primes.into_iter().for_each(&mut f)
+    });
+    let searched = chunks.saturating_mul(options.chunk_size.max(1));
+    Some(end.min(start.saturating_add(searched.checked_sub(1)?)))
+}
+
+/// Like `find_primes_in_range`, but calls `f` once per chunk with the index of
+/// the chunk and its primes. Chunk `i` covers `start + i * chunk_size` up to
+/// the start of the next chunk.
+pub fn find_chunks_in_range<F>(start: u64, end: u64, options: &SearchOptions, f: F)
+where
+    F: FnMut(u64, Vec<u64>),
+{
+    if start > end {
+        return;
+    }
+    let searcher = ChunkSearcher::new(start, end, options.method);
+    map_chunks_in_range(start, end, options, |lo, hi| searcher.primes(lo, hi), f);
+}
+
+/// Splits `[start, end]` into chunks like `find_chunks_in_range`, calls `map`
+/// with the bounds of each chunk on the search threads and then `f` with the
+/// index of each chunk and what `map` returned for it, in ascending order.
+pub fn map_chunks_in_range<T, M, F>(start: u64, end: u64, options: &SearchOptions, map: M, f: F)
+where
+    T: Send,
+    M: Fn(u64, u64) -> T + Sync,
+    F: FnMut(u64, T),
+{
+    map_chunks_until(start, end, options, &CancelToken::new(), map, f);
+}
+
+/// Like `map_chunks_in_range`, but stops handing out chunks once `cancel` is
+/// cancelled. Returns the number of chunks passed to `f`, which are always the
+/// first ones.
+pub fn map_chunks_until<T, M, F>(
+    start: u64,
+    end: u64,
+    options: &SearchOptions,
+    cancel: &CancelToken,
+    map: M,
+    mut f: F,
+) -> u64
+where
+    T: Send,
+    M: Fn(u64, u64) -> T + Sync,
+    F: FnMut(u64, T),
+{
+    if start > end {
+        return 0;
+    }
+    let threads = options.threads.max(1);
+    let chunk_size = options.chunk_size.max(1);
+    let queue = WorkQueue::new((end - start) / chunk_size + 1, threads * CHUNKS_IN_FLIGHT);
+    let (tx, rx) = mpsc::channel::<(u64, T)>();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let (map, queue) = (&map, &queue);
+            scope.spawn(move || {
+                while let Some(chunk) = queue.take(cancel) {
+                    let lo = start + chunk * chunk_size;
+                    let hi = end.min(lo.saturating_add(chunk_size - 1));
+                    tx.send((chunk, map(lo, hi))).unwrap();
+                }
+            });
+        }
+        drop(tx);
+
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (chunk, result) in rx {
+            pending.insert(chunk, result);
+            while let Some(result) = pending.remove(&next) {
+                f(next, result);
+                next += 1;
+                queue.set_emitted(next);
+            }
+        }
+        next
+    })
+}
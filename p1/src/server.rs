@@ -0,0 +1,234 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Mutex, RwLock};
+use std::thread;
+
+use crate::primality;
+use crate::primes::{self, Primes};
+use crate::search::{self, SearchOptions};
+use crate::table::PrimeTable;
+
+/// Widest range a `RANGE` request may ask for.
+const MAX_RANGE_SPAN: u64 = 1 << 20;
+
+/// Widest part of a `COUNT` request beyond the cache limit, which has to be
+/// searched for every request.
+const MAX_UNCACHED_SPAN: u64 = 1 << 30;
+
+/// Tunables of the query server.
+#[derive(Clone, Copy, Debug)]
+pub struct ServerOptions {
+    /// Number of threads used to extend the cache.
+    pub threads: u64,
+    /// Numbers sieved into the cache before accepting clients.
+    pub cache: u64,
+    /// Numbers the cache may grow to when asked about larger ones. Queries
+    /// beyond it are answered without the cache.
+    pub cache_limit: u64,
+}
+
+impl ServerOptions {
+    pub fn new(threads: u64) -> ServerOptions {
+        ServerOptions {
+            threads,
+            cache: 1 << 24,
+            cache_limit: 1 << 32,
+        }
+    }
+}
+
+/// Table of the primes shared by all clients, extended on demand.
+struct Cache {
+    table: RwLock<PrimeTable>,
+    /// Held while extending the table so that only one client sieves.
+    growing: Mutex<()>,
+    options: ServerOptions,
+}
+
+impl Cache {
+    fn new(options: &ServerOptions) -> Cache {
+        let search = SearchOptions::new(options.threads);
+        let cache = options.cache.min(options.cache_limit);
+        Cache {
+            table: RwLock::new(PrimeTable::new(cache, &search)),
+            growing: Mutex::new(()),
+            options: *options,
+        }
+    }
+
+    /// Extends the table so that it covers `n`, at least doubling it so that
+    /// requests creeping upwards don't sieve every time. Returns false if `n`
+    /// is beyond the cache limit.
+    fn cover(&self, n: u64) -> bool {
+        if n >= self.options.cache_limit {
+            return false;
+        }
+        if n < self.table.read().unwrap().end() {
+            return true;
+        }
+        let _growing = self.growing.lock().unwrap();
+        let from = self.table.read().unwrap().end();
+        if n < from {
+            return true;
+        }
+        let end = n
+            .saturating_add(1)
+            .max(from.saturating_mul(2))
+            .min(self.options.cache_limit);
+        let search = SearchOptions::new(self.options.threads);
+        let words = PrimeTable::sieve_words(from, end, &search);
+        self.table.write().unwrap().append(words);
+        eprintln!("Cache extended to {}", end);
+        true
+    }
+
+    fn is_prime(&self, n: u64) -> bool {
+        match self.cover(n) {
+            true => self.table.read().unwrap().is_prime(n),
+            false => primality::is_prime(n),
+        }
+    }
+
+    fn next(&self, n: u64) -> Option<u64> {
+        let cached = match self.cover(n.saturating_add(1)) {
+            true => self.table.read().unwrap().next(n),
+            false => None,
+        };
+        cached.or_else(|| primes::next_prime(n))
+    }
+
+    fn prev(&self, n: u64) -> Option<u64> {
+        match self.cover(n) {
+            true => self.table.read().unwrap().prev(n),
+            false => primes::prev_prime(n),
+        }
+    }
+
+    /// Counts the primes in `[a, b]`, searching the part beyond the cache.
+    fn count(&self, a: u64, b: u64) -> Result<u64, &'static str> {
+        let limit = self.options.cache_limit;
+        let uncached = match b >= limit {
+            true => {
+                let lo = a.max(limit);
+                if b - lo >= MAX_UNCACHED_SPAN {
+                    return Err("range too far beyond the cache");
+                }
+                let mut count = 0;
+                let options = SearchOptions::new(self.options.threads);
+                search::find_primes_in_range(lo, b, &options, |_| count += 1);
+                count
+            }
+            false => 0,
+        };
+        if a >= limit {
+            return Ok(uncached);
+        }
+        let b = b.min(limit - 1);
+        self.cover(b);
+        let table = self.table.read().unwrap();
+        let below = match a {
+            0 => 0,
+            a => table.count(a - 1),
+        };
+        Ok(table.count(b) - below + uncached)
+    }
+
+    fn range(&self, a: u64, b: u64) -> Result<Vec<u64>, &'static str> {
+        if b - a >= MAX_RANGE_SPAN {
+            return Err("range too large");
+        }
+        if !self.cover(b) {
+            return Ok(Primes::new(a, b).collect());
+        }
+        let table = self.table.read().unwrap();
+        let mut primes = Vec::new();
+        let mut p = a.checked_sub(1).map_or(Some(2), |n| table.next(n));
+        while let Some(q) = p.filter(|q| *q <= b) {
+            primes.push(q);
+            p = table.next(q);
+        }
+        Ok(primes)
+    }
+
+    /// Answers one request line.
+    fn answer(&self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let numbers: Result<Vec<u64>, _> = words.iter().skip(1).map(|x| x.parse()).collect();
+        let numbers = match numbers {
+            Ok(numbers) => numbers,
+            Err(_) => return "ERROR: Value not a number.".to_string(),
+        };
+        let command = words.first().map(|x| x.to_ascii_uppercase());
+        let result = match (command.as_deref(), numbers.as_slice()) {
+            (Some("ISPRIME"), [n]) => Ok(match self.is_prime(*n) {
+                true => "YES".to_string(),
+                false => "NO".to_string(),
+            }),
+            (Some("NEXT"), [n]) => Ok(self.next(*n).map_or("NONE".to_string(), |p| p.to_string())),
+            (Some("PREV"), [n]) => Ok(self.prev(*n).map_or("NONE".to_string(), |p| p.to_string())),
+            (Some("COUNT" | "RANGE"), [a, b]) if a > b => {
+                Err("start of the range is after its end")
+            }
+            (Some("COUNT"), [a, b]) => self.count(*a, *b).map(|x| x.to_string()),
+            (Some("RANGE"), [a, b]) => self.range(*a, *b).map(|primes| {
+                let primes: Vec<String> = primes.iter().map(|p| p.to_string()).collect();
+                primes.join(" ")
+            }),
+            (Some("ISPRIME" | "NEXT" | "PREV" | "COUNT" | "RANGE"), _) => {
+                Err("wrong number of arguments")
+            }
+            _ => Err("unknown command"),
+        };
+        result.unwrap_or_else(|e| format!("ERROR: {}", e))
+    }
+}
+
+/// Answers the requests of one client, one line each, until it disconnects.
+fn serve(cache: &Cache, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? != 0 {
+        writeln!(writer, "{}", cache.answer(line.trim()))?;
+        // Answer everything already received before waiting for more.
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+        line.clear();
+    }
+    writer.flush()
+}
+
+/// Answers prime queries from the clients connecting to `listener`, each on
+/// its own thread. Runs until the process exits.
+///
+/// Every request is a line holding one of `ISPRIME n`, `NEXT n`, `PREV n`,
+/// `COUNT a b` or `RANGE a b`, answered with `YES` or `NO`, a prime or `NONE`,
+/// a count or the primes separated by spaces, or `ERROR: <reason>`. The
+/// primes are looked up in a table shared by all clients, which starts at
+/// `options.cache` numbers and grows up to `options.cache_limit` as clients
+/// ask about larger numbers.
+pub fn run_server(listener: TcpListener, options: &ServerOptions) {
+    let cache = Cache::new(options);
+    eprintln!(
+        "Cache holds the primes below {}",
+        cache.table.read().unwrap().end()
+    );
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let (stream, addr) = match stream.and_then(|s| Ok((s.peer_addr()?, s))) {
+                Ok((addr, stream)) => (stream, addr),
+                Err(e) => {
+                    eprintln!("Failed to accept client: {}", e);
+                    continue;
+                }
+            };
+            eprintln!("Client {} connected", addr);
+            let cache = &cache;
+            scope.spawn(move || match serve(cache, stream) {
+                Ok(()) => eprintln!("Client {} disconnected", addr),
+                Err(e) => eprintln!("Client {} dropped: {}", addr, e),
+            });
+        }
+    });
+}
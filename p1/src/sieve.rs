@@ -0,0 +1,219 @@
+/// Number of 64-bit words in one segment. 4096 words is 32 KiB, which keeps the
+/// working set of a segment inside the L1 data cache.
+const SEGMENT_WORDS: usize = 4096;
+
+/// Number of odd candidates covered by one segment.
+const SEGMENT_BITS: usize = SEGMENT_WORDS * 64;
+
+/// Amount of the number line covered by one segment (odd candidates only).
+pub const SEGMENT_SPAN: u64 = SEGMENT_BITS as u64 * 2;
+
+/// Returns every prime `<= limit` using a plain sieve of Eratosthenes. Only
+/// suitable for small limits; used to seed the segmented sieve.
+fn simple_primes(limit: u32) -> Vec<u32> {
+    let limit = limit as usize;
+    if limit < 2 {
+        return Vec::new();
+    }
+
+    // composite[i] describes the odd number 2i + 1.
+    let mut composite = vec![false; limit / 2 + 1];
+    let mut i = 1;
+    while (2 * i + 1) * (2 * i + 1) <= limit {
+        if !composite[i] {
+            let p = 2 * i + 1;
+            let mut j = p * p / 2;
+            while j < composite.len() {
+                composite[j] = true;
+                j += p;
+            }
+        }
+        i += 1;
+    }
+
+    let mut primes = vec![2];
+    primes.extend(
+        (1..composite.len())
+            .filter(|i| !composite[*i] && 2 * i < limit)
+            .map(|i| (2 * i + 1) as u32),
+    );
+    primes
+}
+
+/// Returns every prime `<= limit`. These are the base primes which cross out
+/// composites in the segments; they are themselves found with the segmented
+/// sieve so that limits up to `u32::MAX` don't need a huge flat buffer.
+pub fn base_primes(limit: u32) -> Vec<u32> {
+    let small = simple_primes(isqrt(limit as u64) as u32);
+    let mut primes = Vec::new();
+    SegmentedSieve::new(&small).for_each_prime(0, limit as u64, |p| primes.push(p as u32));
+    primes
+}
+
+/// Integer square root, rounded down.
+pub fn isqrt(v: u64) -> u64 {
+    let mut r = ((v as f64).sqrt() as u64).min(u32::MAX as u64);
+    while r * r > v {
+        r -= 1;
+    }
+    while r < u32::MAX as u64 && (r + 1) * (r + 1) <= v {
+        r += 1;
+    }
+    r
+}
+
+/// Segmented, bit-packed, odd-only sieve of Eratosthenes.
+///
+/// Only odd numbers are stored, one bit each, and the range is processed one
+/// segment of `SEGMENT_SPAN` numbers at a time, so memory use is bounded by the
+/// segment buffer and the base primes no matter how wide the range is.
+pub struct SegmentedSieve<'a> {
+    base: &'a [u32],
+    bits: Vec<u64>,
+}
+
+impl<'a> SegmentedSieve<'a> {
+    /// Creates a sieve using `base`, which must contain every prime up to the
+    /// square root of the largest number that will be sieved.
+    pub fn new(base: &'a [u32]) -> SegmentedSieve<'a> {
+        SegmentedSieve {
+            base,
+            bits: vec![0; SEGMENT_WORDS],
+        }
+    }
+
+    /// Calls `f` with every prime in `[start, end]`, in ascending order.
+    pub fn for_each_prime<F>(&mut self, start: u64, end: u64, mut f: F)
+    where
+        F: FnMut(u64),
+    {
+        if start > end || end < 2 {
+            return;
+        }
+        if start <= 2 {
+            f(2);
+        }
+
+        // First odd number in the range which is not 1.
+        let mut lo = start.max(3) | 1;
+        while lo <= end {
+            let hi = end.min(lo.saturating_add(SEGMENT_SPAN - 2));
+            self.sieve_segment(lo, hi);
+
+            let count = ((hi - lo) / 2 + 1) as usize;
+            for (w, word) in self.bits[..count.div_ceil(64)].iter().enumerate() {
+                let mut free = !word;
+                while free != 0 {
+                    let i = w * 64 + free.trailing_zeros() as usize;
+                    if i >= count {
+                        break;
+                    }
+                    f(lo + 2 * i as u64);
+                    free &= free - 1;
+                }
+            }
+
+            lo = match hi.checked_add(2) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+    }
+
+    /// Marks the composites among the odd numbers in `[lo, hi]`. `lo` must be
+    /// odd and the range must fit in one segment.
+    fn sieve_segment(&mut self, lo: u64, hi: u64) {
+        let count = ((hi - lo) / 2 + 1) as usize;
+        self.bits[..count.div_ceil(64)].fill(0);
+
+        for &p in self.base.iter().skip(1) {
+            let p = p as u64;
+            let square = p * p;
+            if square > hi {
+                break;
+            }
+
+            // First odd multiple of p in the segment, but never p itself.
+            let mut first = if square >= lo {
+                square
+            } else {
+                match lo.div_ceil(p).checked_mul(p) {
+                    Some(x) => x,
+                    None => continue,
+                }
+            };
+            if first % 2 == 0 {
+                first = match first.checked_add(p) {
+                    Some(x) => x,
+                    None => continue,
+                };
+            }
+
+            let mut i = ((first - lo) / 2) as usize;
+            while i < count {
+                self.bits[i / 64] |= 1 << (i % 64);
+                i += p as usize;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primality::trial_division;
+
+    fn sieve(base: &[u32], start: u64, end: u64) -> Vec<u64> {
+        let mut primes = Vec::new();
+        SegmentedSieve::new(base).for_each_prime(start, end, |p| primes.push(p));
+        primes
+    }
+
+    fn trial(start: u64, end: u64) -> Vec<u64> {
+        (start..=end).filter(|v| trial_division(*v)).collect()
+    }
+
+    #[test]
+    fn matches_trial_division_across_segments() {
+        let end = 2 * SEGMENT_SPAN + 1000;
+        let base = base_primes(isqrt(end) as u32);
+        let primes = trial(0, end);
+        assert_eq!(sieve(&base, 0, end), primes);
+        for boundary in [SEGMENT_SPAN, 2 * SEGMENT_SPAN] {
+            for v in boundary - 3..=boundary + 3 {
+                let below: Vec<u64> = primes.iter().copied().filter(|p| *p <= v).collect();
+                assert_eq!(sieve(&base, 1, v), below, "[1, {}]", v);
+                assert_eq!(sieve(&base, v, v + 500), trial(v, v + 500), "[{}, +500]", v);
+            }
+        }
+    }
+
+    #[test]
+    fn small_ranges() {
+        let base = base_primes(100);
+        for start in 0..=3 {
+            for end in 0..=60 {
+                assert_eq!(sieve(&base, start, end), trial(start, end));
+            }
+        }
+        for v in 0..2000 {
+            assert_eq!(sieve(&base, v, v), trial(v, v), "[{}, {}]", v, v);
+        }
+        assert_eq!(sieve(&base, 10, 5), []);
+    }
+
+    #[test]
+    fn ranges_ending_at_u64_max() {
+        // Sieving up to u64::MAX needs every prime below 2^32, so use a short
+        // base and expect the numbers without a factor in it.
+        let base = base_primes(1000);
+        let start = u64::MAX - 2000;
+        let rough: Vec<u64> = (start..=u64::MAX)
+            .filter(|v| base.iter().all(|p| !v.is_multiple_of(*p as u64)))
+            .collect();
+        assert_eq!(sieve(&base, start, u64::MAX), rough);
+        assert!(rough.contains(&18446744073709551557));
+        assert_eq!(sieve(&base, u64::MAX, u64::MAX), []);
+        assert_eq!(sieve(&base, u64::MAX - 1, u64::MAX), []);
+    }
+}
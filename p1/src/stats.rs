@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+
+/// Two consecutive primes with no prime between them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gap {
+    pub lo: u64,
+    pub hi: u64,
+}
+
+impl Gap {
+    pub fn size(&self) -> u64 {
+        self.hi - self.lo
+    }
+}
+
+// Each statistic takes the search to run as `search`, which must call the
+// function it is given with every prime of the range in ascending order. This
+// lets the same statistics run over a plain, checkpointed or resumed search.
+
+/// Counts the primes found by `search`, i.e. pi(end) - pi(start - 1).
+pub fn count<S>(search: S) -> u64
+where
+    S: FnOnce(&mut dyn FnMut(u64)),
+{
+    let mut count = 0;
+    search(&mut |_| count += 1);
+    count
+}
+
+/// Sums the primes found by `search`.
+pub fn sum<S>(search: S) -> u128
+where
+    S: FnOnce(&mut dyn FnMut(u64)),
+{
+    let mut sum = 0;
+    search(&mut |p| sum += p as u128);
+    sum
+}
+
+/// Calls `f` with every pair of primes `(p, p + distance)` found by `search`,
+/// ordered by `p`. A distance of 2 gives twin primes, 4 cousin primes and 6
+/// sexy primes. Returns the number of pairs.
+pub fn pairs<S, F>(search: S, distance: u64, mut f: F) -> u64
+where
+    S: FnOnce(&mut dyn FnMut(u64)),
+    F: FnMut(u64, u64),
+{
+    // Primes no further than `distance` behind the latest one.
+    let mut recent: VecDeque<u64> = VecDeque::new();
+    let mut count = 0;
+    search(&mut |p| {
+        while recent.front().is_some_and(|x| p - x > distance) {
+            recent.pop_front();
+        }
+        if recent.front().is_some_and(|x| p - x == distance) {
+            f(p - distance, p);
+            count += 1;
+        }
+        recent.push_back(p);
+    });
+    count
+}
+
+/// Calls `f` with every record gap between consecutive primes found by
+/// `search`, i.e. each gap longer than all gaps before it. The last gap
+/// reported is the maximal gap of the range, which is also returned.
+pub fn record_gaps<S, F>(search: S, mut f: F) -> Option<Gap>
+where
+    S: FnOnce(&mut dyn FnMut(u64)),
+    F: FnMut(Gap),
+{
+    let mut previous = None;
+    let mut record: Option<Gap> = None;
+    search(&mut |p| {
+        if let Some(lo) = previous {
+            let gap = Gap { lo, hi: p };
+            if record.is_none_or(|x| gap.size() > x.size()) {
+                f(gap);
+                record = Some(gap);
+            }
+        }
+        previous = Some(p);
+    });
+    record
+}
@@ -0,0 +1,515 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+use memmap2::{Mmap, MmapMut};
+
+use crate::search::{self, SearchOptions};
+
+/// Numbers covered by one word of the table, which holds a bit for each of
+/// their 64 odd numbers.
+const WORD_SPAN: u64 = 128;
+
+/// Words between two of the running prime counts kept for counting.
+const BLOCK_WORDS: usize = 8;
+
+/// Words covered by each checksum of a table file, a multiple of
+/// `BLOCK_WORDS`. Checking one costs hashing 64 KiB, whatever the size of the
+/// table.
+const CHECK_WORDS: usize = 1 << 13;
+
+const MAGIC: &[u8; 4] = b"PTBL";
+const VERSION: u8 = 2;
+
+/// Magic, version and end.
+const HEADER_LEN: usize = 13;
+
+/// Sizes of the sections of a table file: the header, the words of bits, a
+/// running count for each block and a checksum for each `CHECK_WORDS` words.
+#[derive(Clone, Copy)]
+struct Layout {
+    words: usize,
+    blocks: usize,
+    checks: usize,
+}
+
+impl Layout {
+    /// Returns the layout of a table of `words` words, or `None` if the file
+    /// couldn't be addressed.
+    fn new(words: u64) -> Option<Layout> {
+        let layout = Layout {
+            words: words.try_into().ok()?,
+            blocks: words.div_ceil(BLOCK_WORDS as u64).try_into().ok()?,
+            checks: words.div_ceil(CHECK_WORDS as u64).try_into().ok()?,
+        };
+        let sections = layout.words.checked_add(layout.blocks)?;
+        sections.checked_add(layout.checks)?.checked_mul(8)?;
+        Some(layout)
+    }
+
+    /// Offset of the counts in the file.
+    fn counts(&self) -> usize {
+        HEADER_LEN + self.words * 8
+    }
+
+    /// Offset of the checksums in the file.
+    fn sums(&self) -> usize {
+        self.counts() + self.blocks * 8
+    }
+
+    fn len(&self) -> usize {
+        self.sums() + self.checks * 8
+    }
+}
+
+fn read_word(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Read access to the words a table is stored in.
+pub trait Words {
+    fn word(&self, i: usize) -> u64;
+    fn word_count(&self) -> usize;
+}
+
+impl Words for Vec<u64> {
+    fn word(&self, i: usize) -> u64 {
+        self[i]
+    }
+
+    fn word_count(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Little-endian words stored in part of a memory-mapped table file.
+pub struct MappedWords {
+    map: Arc<Mmap>,
+    offset: usize,
+    count: usize,
+}
+
+impl Words for MappedWords {
+    fn word(&self, i: usize) -> u64 {
+        assert!(i < self.count);
+        read_word(&self.map, self.offset + i * 8)
+    }
+
+    fn word_count(&self) -> usize {
+        self.count
+    }
+}
+
+/// Bit-packed table of the primes below `end()`, supporting constant time
+/// primality lookups and prime counting.
+///
+/// Bit `j` of word `w` is set when `128w + 2j + 1` is prime; 2 is implied.
+/// Tables are either sieved in memory or built in a file with `build`, which
+/// can later be opened memory-mapped instead of sieving again.
+#[derive(Default)]
+pub struct PrimeTable<W = Vec<u64>> {
+    bits: W,
+    /// Number of odd primes below the start of each block of words.
+    counts: W,
+}
+
+impl PrimeTable {
+    /// Sieves a table of at least the primes below `end`.
+    pub fn new(end: u64, options: &SearchOptions) -> PrimeTable {
+        let mut table = PrimeTable::default();
+        table.extend(end, options);
+        table
+    }
+
+    /// Sieves the numbers from `end()` on so that at least the primes below
+    /// `end` are in the table.
+    pub fn extend(&mut self, end: u64, options: &SearchOptions) {
+        let words = PrimeTable::sieve_words(self.end(), end, options);
+        self.append(words);
+    }
+
+    /// Returns the words of the table covering `[from, end)` and rounded up
+    /// to a whole block, where `from` is the `end()` of the table to append
+    /// them to. Done separately from `append` so that a shared table can be
+    /// extended without locking it while sieving.
+    pub(crate) fn sieve_words(from: u64, end: u64, options: &SearchOptions) -> Vec<u64> {
+        let block_span = BLOCK_WORDS as u64 * WORD_SPAN;
+        let to = end.div_ceil(block_span).saturating_mul(block_span);
+        if to <= from {
+            return Vec::new();
+        }
+        let mut words = vec![0u64; ((to - from) / WORD_SPAN) as usize];
+        search::find_primes_in_range(from, to - 1, options, |p| {
+            if p != 2 {
+                let i = (p - from) / 2;
+                words[(i / 64) as usize] |= 1 << (i % 64);
+            }
+        });
+        words
+    }
+
+    /// Appends words made by `sieve_words` for this table's `end()`.
+    pub(crate) fn append(&mut self, words: Vec<u64>) {
+        self.bits.extend(words);
+        while self.counts.len() * BLOCK_WORDS < self.bits.len() {
+            let count = match self.counts.len() {
+                0 => 0,
+                b => self.counts[b - 1] + self.block_count(b - 1, BLOCK_WORDS),
+            };
+            self.counts.push(count);
+        }
+    }
+}
+
+impl PrimeTable<MappedWords> {
+    /// Sieves a table of at least the primes below `end` into a new file at
+    /// `path` and opens it. The primes are set in a writable map of the file
+    /// as they are found, and the counts and checksums filled in behind them,
+    /// so the table never has to fit in memory.
+    pub fn build(
+        path: &str,
+        end: u64,
+        options: &SearchOptions,
+    ) -> io::Result<PrimeTable<MappedWords>> {
+        let blocks = end.div_ceil(BLOCK_WORDS as u64 * WORD_SPAN);
+        let words = blocks * BLOCK_WORDS as u64;
+        let layout = Layout::new(words)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "prime table is too large"))?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(layout.len() as u64)?;
+        // Safety: see `open`; the file was just created and nothing else
+        // writes to it.
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        let mut writer = TableWriter {
+            map,
+            layout,
+            checked: 0,
+            count: 0,
+        };
+        let table_end = words.saturating_mul(WORD_SPAN);
+        writer.map[..4].copy_from_slice(MAGIC);
+        writer.map[4] = VERSION;
+        writer.map[5..HEADER_LEN].copy_from_slice(&table_end.to_le_bytes());
+
+        if table_end > 0 {
+            search::find_primes_in_range(0, table_end - 1, options, |p| {
+                if p != 2 {
+                    writer.set(p / 2);
+                }
+            });
+        }
+        writer.fill(layout.words);
+        writer.map.flush()?;
+        drop(writer);
+        file.sync_all()?;
+        PrimeTable::open(path)
+    }
+
+    /// Maps the table file at `path` into memory, rejecting it if it isn't a
+    /// table of this version or is truncated. The checksums aren't read, so
+    /// this takes constant time; use `check` before trusting a lookup.
+    pub fn open(path: &str) -> io::Result<PrimeTable<MappedWords>> {
+        let file = File::open(path)?;
+        // Safety: the table is only read through the map, which would be
+        // undefined behaviour if the file were modified meanwhile. Table files
+        // are written once by `build` and never changed in place.
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+        let invalid = |message: String| io::Error::new(ErrorKind::InvalidData, message);
+        if map.len() < HEADER_LEN || &map[..4] != MAGIC {
+            return Err(invalid("not a prime table".to_string()));
+        }
+        if map[4] != VERSION {
+            return Err(invalid(format!(
+                "unsupported prime table version {}",
+                map[4]
+            )));
+        }
+        let end = read_word(&map, 5);
+        let whole = end.is_multiple_of(BLOCK_WORDS as u64 * WORD_SPAN);
+        let layout = Layout::new(end / WORD_SPAN).filter(|x| x.len() == map.len());
+        let layout = match layout {
+            Some(layout) if whole => layout,
+            _ => {
+                return Err(invalid(
+                    "prime table is truncated or has trailing data".to_string(),
+                ))
+            }
+        };
+        Ok(PrimeTable {
+            bits: MappedWords {
+                map: map.clone(),
+                offset: HEADER_LEN,
+                count: layout.words,
+            },
+            counts: MappedWords {
+                map,
+                offset: layout.counts(),
+                count: layout.blocks,
+            },
+        })
+    }
+
+    /// Checks the checksum of the part of the table that looking up `n`,
+    /// which must be below `end()`, reads.
+    pub fn check(&self, n: u64) -> io::Result<()> {
+        let k = (n / WORD_SPAN) as usize / CHECK_WORDS;
+        let first = k * CHECK_WORDS;
+        let last = (first + CHECK_WORDS).min(self.bits.count);
+        let bits = (first..last).map(|i| self.bits.word(i));
+        let counts = (first / BLOCK_WORDS..last / BLOCK_WORDS).map(|b| self.counts.word(b));
+        let sums = self.counts.offset + self.counts.count * 8;
+        if checksum(bits.chain(counts)) != read_word(&self.counts.map, sums + k * 8) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "prime table checksum mismatch in words {} to {}",
+                    first, last
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Fills in a table file mapped for writing as `build` finds its primes.
+struct TableWriter {
+    map: MmapMut,
+    layout: Layout,
+    /// Number of `CHECK_WORDS` parts with their counts and checksum written.
+    checked: usize,
+    /// Number of odd primes before the first part which isn't checked yet.
+    count: u64,
+}
+
+impl TableWriter {
+    /// Sets the bit with index `i`, which must not be before any bit set
+    /// earlier, completing every part before it.
+    fn set(&mut self, i: u64) {
+        let i = i as usize;
+        self.fill(i / 64);
+        self.map[HEADER_LEN + i / 8] |= 1 << (i % 8);
+    }
+
+    /// Writes the counts and checksum of every part which ends by word `w`.
+    fn fill(&mut self, w: usize) {
+        while self.checked < self.layout.checks {
+            let first = self.checked * CHECK_WORDS;
+            let last = (first + CHECK_WORDS).min(self.layout.words);
+            if last > w {
+                return;
+            }
+            let bits: Vec<u64> = (first..last)
+                .map(|i| read_word(&self.map, HEADER_LEN + i * 8))
+                .collect();
+            let mut counts = Vec::new();
+            for block in bits.chunks(BLOCK_WORDS) {
+                counts.push(self.count);
+                self.count += block.iter().map(|x| x.count_ones() as u64).sum::<u64>();
+            }
+            let at = self.layout.counts() + first / BLOCK_WORDS * 8;
+            for (b, count) in counts.iter().enumerate() {
+                self.map[at + b * 8..at + b * 8 + 8].copy_from_slice(&count.to_le_bytes());
+            }
+            let sum = checksum(bits.into_iter().chain(counts));
+            let at = self.layout.sums() + self.checked * 8;
+            self.map[at..at + 8].copy_from_slice(&sum.to_le_bytes());
+            self.checked += 1;
+        }
+    }
+}
+
+/// FNV-1a over whole words, enough to catch damaged tables.
+fn checksum<I: Iterator<Item = u64>>(words: I) -> u64 {
+    words.fold(0xcbf29ce484222325, |hash, word| {
+        (hash ^ word).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl<W: Words> PrimeTable<W> {
+    /// Returns the number below which every prime is in the table.
+    pub fn end(&self) -> u64 {
+        self.bits.word_count() as u64 * WORD_SPAN
+    }
+
+    /// Number of primes in the first `words` words of block `b`.
+    fn block_count(&self, b: usize, words: usize) -> u64 {
+        let first = b * BLOCK_WORDS;
+        (first..first + words)
+            .map(|i| self.bits.word(i).count_ones() as u64)
+            .sum()
+    }
+
+    /// Returns whether `n`, which must be below `end()`, is prime.
+    pub fn is_prime(&self, n: u64) -> bool {
+        if n.is_multiple_of(2) {
+            return n == 2;
+        }
+        let i = n / 2;
+        self.bits.word((i / 64) as usize) & (1 << (i % 64)) != 0
+    }
+
+    /// Returns the number of primes up to and including `n`, which must be
+    /// below `end()`.
+    pub fn count(&self, n: u64) -> u64 {
+        if n < 2 {
+            return 0;
+        }
+        let w = (n / WORD_SPAN) as usize;
+        let b = w / BLOCK_WORDS;
+        // Odd numbers of word `w` up to `n`.
+        let odd = (n % WORD_SPAN).div_ceil(2);
+        let mask = match odd {
+            64 => u64::MAX,
+            odd => (1 << odd) - 1,
+        };
+        let partial = (self.bits.word(w) & mask).count_ones() as u64;
+        1 + self.counts.word(b) + self.block_count(b, w - b * BLOCK_WORDS) + partial
+    }
+
+    /// Returns the smallest prime greater than `n`, or `None` if there is none
+    /// in the table.
+    pub fn next(&self, n: u64) -> Option<u64> {
+        if n < 2 {
+            return Some(2).filter(|x| *x < self.end());
+        }
+        // Index of the smallest odd number greater than `n`.
+        let i = n / 2 + n % 2;
+        let words = self.bits.word_count();
+        let mut w = (i / 64) as usize;
+        if w >= words {
+            return None;
+        }
+        let mut word = self.bits.word(w) & (u64::MAX << (i % 64));
+        while word == 0 {
+            w += 1;
+            if w == words {
+                return None;
+            }
+            word = self.bits.word(w);
+        }
+        Some((w as u64 * 64 + word.trailing_zeros() as u64) * 2 + 1)
+    }
+
+    /// Returns the largest prime smaller than `n`, which must be at most
+    /// `end()`, or `None` if `n <= 2`.
+    pub fn prev(&self, n: u64) -> Option<u64> {
+        if n <= 3 {
+            return Some(2).filter(|_| n == 3);
+        }
+        // Index of the largest odd number smaller than `n`.
+        let i = n / 2 - 1;
+        let mut w = (i / 64) as usize;
+        let mut word = self.bits.word(w) & (u64::MAX >> (63 - i % 64));
+        while word == 0 {
+            if w == 0 {
+                return Some(2);
+            }
+            w -= 1;
+            word = self.bits.word(w);
+        }
+        Some((w as u64 * 64 + 63 - word.leading_zeros() as u64) * 2 + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primality::trial_division;
+    use std::fs;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("p1-{}-{}", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn lookups_at_word_and_block_boundaries() {
+        let table = PrimeTable::new(5000, &SearchOptions::new(2));
+        let end = table.end();
+        assert_eq!(end, 5 * BLOCK_WORDS as u64 * WORD_SPAN);
+        let primes: Vec<u64> = (0..end).filter(|v| trial_division(*v)).collect();
+        for n in 0..end {
+            let below = primes.partition_point(|p| *p < n);
+            let up_to = primes.partition_point(|p| *p <= n);
+            assert_eq!(table.is_prime(n), trial_division(n), "{}", n);
+            assert_eq!(table.count(n), up_to as u64, "{}", n);
+            assert_eq!(table.next(n), primes.get(up_to).copied(), "{}", n);
+            let prev = below.checked_sub(1).map(|i| primes[i]);
+            assert_eq!(table.prev(n), prev, "{}", n);
+        }
+        assert_eq!(table.next(end - 1), None);
+        assert_eq!(table.prev(end), primes.last().copied());
+    }
+
+    #[test]
+    fn built_tables_match_sieved_ones() {
+        let options = SearchOptions::new(2);
+        let end = 2 * CHECK_WORDS as u64 * WORD_SPAN + 5000;
+        let path = temp_path("table-built");
+        let built = PrimeTable::build(&path, end, &options).unwrap();
+        let sieved = PrimeTable::new(end, &options);
+        assert_eq!(built.end(), sieved.end());
+        assert_eq!(built.bits.word_count(), sieved.bits.len());
+        for i in 0..sieved.bits.len() {
+            assert_eq!(built.bits.word(i), sieved.bits[i], "word {}", i);
+        }
+        assert_eq!(built.counts.word_count(), sieved.counts.len());
+        for b in 0..sieved.counts.len() {
+            assert_eq!(built.counts.word(b), sieved.counts[b], "block {}", b);
+        }
+        for n in (0..built.end()).step_by(CHECK_WORDS * WORD_SPAN as usize / 2) {
+            built.check(n).unwrap();
+        }
+        built.check(built.end() - 1).unwrap();
+        for n in [CHECK_WORDS as u64 * WORD_SPAN, built.end() - 1] {
+            assert_eq!(built.count(n), sieved.count(n));
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let path = temp_path("table-damaged");
+        let span = CHECK_WORDS as u64 * WORD_SPAN;
+        PrimeTable::build(&path, span + 1, &SearchOptions::new(2)).unwrap();
+        let good = fs::read(&path).unwrap();
+        let open = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            PrimeTable::open(&path)
+        };
+
+        let truncated = &good[..good.len() - 1];
+        let mut trailing = good.clone();
+        trailing.push(0);
+        let mut old = good.clone();
+        old[4] = 1;
+        let mut end = good.clone();
+        end[5] ^= 0x80;
+        for bytes in [truncated, &trailing, &old, &end, &good[..3], b"PRMS\x01"] {
+            let e = open(bytes).err().expect("damaged table was opened");
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
+
+        // Only the damaged part fails its check.
+        let mut flipped = good.clone();
+        flipped[HEADER_LEN + CHECK_WORDS * 8 + 20] ^= 4;
+        let table = open(&flipped).unwrap();
+        table.check(span - 1).unwrap();
+        assert_eq!(
+            table.check(span).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        let mut count = good.clone();
+        let layout = Layout::new(table.bits.word_count() as u64).unwrap();
+        count[layout.counts() + 8] ^= 1;
+        let table = open(&count).unwrap();
+        assert!(table.check(0).is_err());
+        table.check(span).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+}
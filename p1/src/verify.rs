@@ -0,0 +1,151 @@
+use crate::search::{self, ChunkSearcher, Method, SearchOptions};
+
+/// pi(10^k), the number of primes up to 10^k, for k from 0 to 19.
+pub const PI_POWERS_OF_TEN: [u64; 20] = [
+    0,
+    4,
+    25,
+    168,
+    1229,
+    9592,
+    78498,
+    664579,
+    5761455,
+    50847534,
+    455052511,
+    4118054813,
+    37607912018,
+    346065536839,
+    3204941750802,
+    29844570422669,
+    279238341033925,
+    2623557157654233,
+    24739954287740860,
+    234057667276344607,
+];
+
+/// Outcome of comparing two methods over a range.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// Number of primes found by both methods.
+    pub agreed: u64,
+    /// Number of numbers found to be prime by only one of the methods.
+    pub disagreements: u64,
+    /// pi(10^k) counted from the primes both methods found, as `(k, count)`
+    /// for every power of ten in the range. Only counted for ranges starting
+    /// at 2 or below.
+    pub powers: Vec<(u32, u64)>,
+}
+
+/// Primes of one chunk found by both methods, and the numbers found by only
+/// one of them along with whether it was the first.
+type Comparison = (Vec<u64>, Vec<(u64, bool)>);
+
+fn compare(first: Vec<u64>, second: Vec<u64>) -> Comparison {
+    let (mut agreed, mut disagreements) = (Vec::new(), Vec::new());
+    let (mut first, mut second) = (first.into_iter().peekable(), second.into_iter().peekable());
+    loop {
+        match (first.peek(), second.peek()) {
+            (Some(p), Some(q)) if p == q => {
+                agreed.push(*p);
+                first.next();
+                second.next();
+            }
+            (Some(p), Some(q)) if p < q => disagreements.push((first.next().unwrap(), true)),
+            (Some(_), None) => disagreements.push((first.next().unwrap(), true)),
+            (_, Some(_)) => disagreements.push((second.next().unwrap(), false)),
+            (None, None) => return (agreed, disagreements),
+        }
+    }
+}
+
+/// Searches `[start, end]` both with `options.method` and independently with
+/// `other`, each chunk on the search threads, and calls `f` with every number
+/// only one of them found to be prime, in ascending order, along with whether
+/// it was `options.method` which did. `searched` is called with the last
+/// number of each chunk once it has been compared.
+pub fn cross_check<F, G>(
+    start: u64,
+    end: u64,
+    options: &SearchOptions,
+    other: Method,
+    mut f: F,
+    mut searched: G,
+) -> Report
+where
+    F: FnMut(u64, bool),
+    G: FnMut(u64),
+{
+    let mut report = Report::default();
+    if start > end {
+        return report;
+    }
+    let first = ChunkSearcher::new(start, end, options.method);
+    let second = ChunkSearcher::new(start, end, other);
+    let search = |lo, hi| compare(first.primes(lo, hi), second.primes(lo, hi));
+
+    // Next power of ten to count the primes up to, if any is left in range.
+    let mut power = (start <= 2).then_some((0u32, 1u64)).filter(|x| x.1 <= end);
+    let mut count_to = |p: u64, report: &mut Report| {
+        while let Some((k, n)) = power.filter(|x| x.1 < p) {
+            report.powers.push((k, report.agreed));
+            power = n.checked_mul(10).filter(|n| *n <= end).map(|n| (k + 1, n));
+        }
+    };
+    let chunk_size = options.chunk_size.max(1);
+    search::map_chunks_in_range(
+        start,
+        end,
+        options,
+        search,
+        |chunk, (agreed, disagreements)| {
+            for p in agreed {
+                count_to(p, &mut report);
+                report.agreed += 1;
+            }
+            for (n, by_first) in disagreements {
+                report.disagreements += 1;
+                f(n, by_first);
+            }
+            let last = (chunk + 1).saturating_mul(chunk_size) - 1;
+            searched(start.saturating_add(last).min(end));
+        },
+    );
+    count_to(u64::MAX, &mut report);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_match_known_pi() {
+        let options = SearchOptions::new(2);
+        for k in 0..=7 {
+            let mut count = 0;
+            search::find_primes_in_range(0, 10u64.pow(k), &options, |_| count += 1);
+            assert_eq!(count, PI_POWERS_OF_TEN[k as usize], "pi(10^{})", k);
+        }
+    }
+
+    #[test]
+    fn sieve_and_miller_rabin_agree() {
+        let options = SearchOptions {
+            method: Method::Sieve,
+            chunk_size: 10_000,
+            ..SearchOptions::new(2)
+        };
+        let report = cross_check(
+            0,
+            200_000,
+            &options,
+            Method::MillerRabin,
+            |n, _| panic!("methods disagree on {}", n),
+            |_| {},
+        );
+        assert_eq!(report.disagreements, 0);
+        let known: Vec<_> = (0..=5).map(|k| (k, PI_POWERS_OF_TEN[k as usize])).collect();
+        assert_eq!(report.powers, known);
+    }
+}
@@ -0,0 +1,252 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Years searched for a matching time before deciding an expression never
+/// matches, such as one only matching February 30th.
+const MAX_YEARS: i64 = 8;
+
+/// A five field cron expression, `minute hour day-of-month month
+/// day-of-week`, evaluated in UTC.
+///
+/// Fields are lists of `*`, numbers and `a-b` ranges, each optionally with a
+/// `/step`. Sunday is either 0 or 7 in the day of week. As in cron, when both
+/// the day of month and the day of week are restricted a day matching either
+/// of them matches.
+#[derive(Clone, Debug)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Why a cron expression couldn't be parsed.
+#[derive(Debug)]
+pub struct ParseCronError(String);
+
+impl fmt::Display for ParseCronError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid cron expression: {}", self.0)
+    }
+}
+
+impl Error for ParseCronError {}
+
+/// Parses one field into a mask with a bit for each value in `[min, max]`
+/// it matches.
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, ParseCronError> {
+    let invalid = || ParseCronError(format!("bad field '{}'", field));
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (lo, hi) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((lo, hi)) => (
+                lo.parse().map_err(|_| invalid())?,
+                hi.parse().map_err(|_| invalid())?,
+            ),
+            // A single value with a step runs to the end of the field.
+            None if step > 1 => (range.parse().map_err(|_| invalid())?, max),
+            None => {
+                let value = range.parse().map_err(|_| invalid())?;
+                (value, value)
+            }
+        };
+        if step == 0 || lo < min || hi > max || lo > hi {
+            return Err(invalid());
+        }
+        for value in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl FromStr for Cron {
+    type Err = ParseCronError;
+
+    fn from_str(s: &str) -> Result<Cron, ParseCronError> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(ParseCronError(format!(
+                "expected 5 fields, got {}",
+                fields.len()
+            )));
+        };
+        let mut weekdays_mask = parse_field(weekdays, 0, 7)?;
+        if weekdays_mask & 1 << 7 != 0 {
+            weekdays_mask |= 1;
+        }
+        Ok(Cron {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekdays_mask,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+}
+
+/// Returns the year, month and day of the day `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month as u64, day as u64)
+}
+
+impl Cron {
+    fn matches_day(&self, days: i64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // 1970-01-01 was a Thursday.
+        let weekday = (days + 4).rem_euclid(7);
+        let by_day = self.days & 1 << day != 0;
+        let by_weekday = self.weekdays & 1 << weekday != 0;
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => by_day || by_weekday,
+            _ => by_day && by_weekday,
+        };
+        self.months & 1 << month != 0 && day_matches
+    }
+
+    /// Returns the first whole minute after `after` which the expression
+    /// matches, or `None` if it doesn't match any in the next few years.
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let after = after.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut minute = (after.as_secs() / 60 + 1) as i64;
+        let (first_year, _, _) = civil_from_days(minute / 1440);
+        loop {
+            let days = minute / 1440;
+            let (hour, min) = (minute % 1440 / 60, minute % 60);
+            if !self.matches_day(days) {
+                if civil_from_days(days).0 - first_year > MAX_YEARS {
+                    return None;
+                }
+                minute = (days + 1) * 1440;
+            } else if self.hours & 1 << hour == 0 {
+                minute += 60 - min;
+            } else if self.minutes & 1 << min == 0 {
+                minute += 1;
+            } else {
+                return Some(UNIX_EPOCH + Duration::from_secs(minute as u64 * 60));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01 00:00 UTC, a Monday.
+    const NEW_YEAR_2024: u64 = 1704067200;
+
+    /// Returns the next `count` times `expr` matches after `from`, in seconds
+    /// since the epoch.
+    fn next(expr: &str, from: u64, count: usize) -> Vec<u64> {
+        let cron: Cron = expr.parse().unwrap();
+        let mut at = UNIX_EPOCH + Duration::from_secs(from);
+        let mut times = Vec::new();
+        while times.len() < count {
+            match cron.next_after(at) {
+                Some(next) => at = next,
+                None => break,
+            }
+            times.push(at.duration_since(UNIX_EPOCH).unwrap().as_secs());
+        }
+        times
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19723), (2024, 1, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn steps_lists_and_ranges() {
+        let t = NEW_YEAR_2024;
+        assert_eq!(next("*/15 * * * *", t, 2), [t + 900, t + 1800]);
+        assert_eq!(next("5/20 * * * *", t, 3), [t + 300, t + 1500, t + 2700]);
+        assert_eq!(next("0,30 * * * *", t, 2), [t + 1800, t + 3600]);
+        assert_eq!(next("0 22-23/1 * * *", t, 2), [t + 79200, t + 82800]);
+        // Weekdays at 9:30, skipping the weekend of the 6th and 7th.
+        let workdays: Vec<u64> = [0, 1, 2, 3, 4, 7]
+            .iter()
+            .map(|d| t + d * 86400 + 34200)
+            .collect();
+        assert_eq!(next("30 9 * * 1-5", t, 6), workdays);
+    }
+
+    #[test]
+    fn only_whole_minutes_after() {
+        let t = NEW_YEAR_2024;
+        assert_eq!(next("* * * * *", t, 1), [t + 60]);
+        assert_eq!(next("* * * * *", t + 59, 1), [t + 60]);
+    }
+
+    #[test]
+    fn sunday_is_0_or_7() {
+        let sunday = NEW_YEAR_2024 + 6 * 86400;
+        assert_eq!(next("0 0 * * 0", NEW_YEAR_2024, 1), [sunday]);
+        assert_eq!(next("0 0 * * 7", NEW_YEAR_2024, 1), [sunday]);
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        let day = |d: u64| NEW_YEAR_2024 + (d - 1) * 86400;
+        // Fridays and the 13th, both restricted.
+        assert_eq!(
+            next("0 0 13 * 5", NEW_YEAR_2024, 4),
+            [day(5), day(12), day(13), day(19)]
+        );
+        // Only the 13th, as the day of week isn't restricted.
+        assert_eq!(next("0 0 13 * *", NEW_YEAR_2024, 1), [day(13)]);
+    }
+
+    #[test]
+    fn leap_days_and_impossible_dates() {
+        let leap_days = [1709164800, 1835395200];
+        assert_eq!(next("0 0 29 2 *", NEW_YEAR_2024, 2), leap_days);
+        assert_eq!(next("0 0 30 2 *", NEW_YEAR_2024, 1), []);
+        assert_eq!(next("0 0 31 4 *", NEW_YEAR_2024, 1), []);
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        for expr in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+            "1,,2 * * * *",
+        ] {
+            assert!(expr.parse::<Cron>().is_err(), "{}", expr);
+        }
+    }
+}
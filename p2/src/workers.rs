@@ -0,0 +1,1454 @@
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::ops::{Add, Sub};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::{self, Instant, SystemTime};
+
+use crate::cron::Cron;
+
+type WorkFunc = dyn FnOnce() + Send;
+
+/// How urgently a job should run once it is due.
+///
+/// Due jobs are raised by a class for every `STARVATION_BOOST` they have
+/// waited, so the boost is relative aging rather than a guarantee of turns: a
+/// `Low` job due in the same burst as `High` jobs still runs after all of
+/// them, and is only sure to overtake those which became due a second or more
+/// after it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    const COUNT: usize = 3;
+}
+
+/// How long a due job has to wait for its priority to be raised by one class,
+/// so that a steady stream of higher priority jobs can't starve it.
+const STARVATION_BOOST: time::Duration = time::Duration::from_millis(500);
+
+struct Work {
+    func: Box<WorkFunc>,
+    when: time::Instant,
+    priority: Priority,
+    label: Option<String>,
+}
+impl Work {
+    fn until(&self) -> time::Duration {
+        let now = Instant::now();
+        if self.when > now {
+            self.when.sub(Instant::now())
+        } else {
+            time::Duration::ZERO
+        }
+    }
+}
+
+/// Deadline of a pending `Work`. Entries of cancelled work, or for deadlines
+/// which have since been rescheduled, are left where they are and skipped
+/// once they are next in line, or all dropped once they outnumber the work.
+#[derive(Clone, Copy)]
+struct Entry {
+    when: time::Instant,
+    id: u64,
+}
+
+/// Returns whether `entry` is the deadline of pending work.
+fn is_current(works: &HashMap<u64, Work>, entry: &Entry) -> bool {
+    matches!(works.get(&entry.id), Some(work) if work.when == entry.when)
+}
+
+/// Pending work, first waiting in a heap until it is due and then in a FIFO
+/// queue for its priority until a thread takes it.
+struct WorkList {
+    heap: BinaryHeap<Entry>,
+    ready: [VecDeque<Entry>; Priority::COUNT],
+    works: HashMap<u64, Work>,
+    next_id: u64,
+}
+impl WorkList {
+    fn new() -> WorkList {
+        WorkList {
+            heap: BinaryHeap::new(),
+            ready: Default::default(),
+            works: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Adds `work`, returning the id to cancel or reschedule it by.
+    fn push(&mut self, work: Work) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.heap.push(Entry {
+            when: work.when,
+            id,
+        });
+        self.works.insert(id, work);
+        id
+    }
+
+    /// Drops every entry which no longer matches pending work once there are
+    /// more of them than of the work, so that work which is rescheduled or
+    /// cancelled over and over doesn't keep growing the heap.
+    fn compact(&mut self) {
+        let ready: usize = self.ready.iter().map(|x| x.len()).sum();
+        if self.heap.len() + ready <= 2 * self.works.len() {
+            return;
+        }
+        let works = &self.works;
+        self.heap.retain(|x| is_current(works, x));
+        for queue in &mut self.ready {
+            queue.retain(|x| is_current(works, x));
+        }
+    }
+
+    /// Moves the work which is due to the ready queues and drops the entries
+    /// at the front of the heap and queues which no longer match pending work.
+    fn promote(&mut self) {
+        let now = Instant::now();
+        while let Some(&top) = self.heap.peek() {
+            if !is_current(&self.works, &top) {
+                self.heap.pop();
+            } else if top.when <= now {
+                self.heap.pop();
+                self.ready[self.works[&top.id].priority as usize].push_back(top);
+            } else {
+                break;
+            }
+        }
+        for i in 0..Priority::COUNT {
+            while let Some(front) = self.ready[i].front() {
+                if is_current(&self.works, front) {
+                    break;
+                }
+                self.ready[i].pop_front();
+            }
+        }
+    }
+
+    /// Takes the due work with the highest priority, after raising each
+    /// priority by a class for every `STARVATION_BOOST` it has waited. Ties go
+    /// to the work which was due first, and then to the one posted first.
+    fn pop(&mut self) -> Option<Work> {
+        self.promote();
+        let now = Instant::now();
+        let boosted = |(priority, entry): (usize, &Entry)| {
+            let waited = now.saturating_duration_since(entry.when);
+            let boost = waited.as_nanos() / STARVATION_BOOST.as_nanos();
+            (priority as u128 + boost, Reverse((entry.when, entry.id)))
+        };
+        let (priority, _) = (0..Priority::COUNT)
+            .filter_map(|i| self.ready[i].front().map(|x| (i, x)))
+            .max_by_key(|x| boosted(*x))?;
+        let entry = self.ready[priority].pop_front().unwrap();
+        self.works.remove(&entry.id)
+    }
+
+    /// Removes the work with `id` if it hasn't started yet.
+    fn cancel(&mut self, id: u64) -> Option<Work> {
+        let work = self.works.remove(&id);
+        self.compact();
+        work
+    }
+
+    /// Moves the deadline of the work with `id` to `when`, returning false if
+    /// it has already started or was cancelled.
+    fn reschedule(&mut self, id: u64, when: time::Instant) -> bool {
+        match self.works.get_mut(&id) {
+            Some(work) if work.when == when => true,
+            Some(work) => {
+                work.when = when;
+                self.heap.push(Entry { when, id });
+                self.compact();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.works.len()
+    }
+
+    /// Removes the work due after `after`, or all work if `None`, returning it
+    /// in the order it was due.
+    fn take(&mut self, after: Option<Instant>) -> Vec<Work> {
+        let mut ids: Vec<(Instant, u64)> = self
+            .works
+            .iter()
+            .filter(|(_, work)| after.is_none_or(|x| work.when > x))
+            .map(|(id, work)| (work.when, *id))
+            .collect();
+        ids.sort();
+        ids.iter()
+            .map(|(_, id)| self.works.remove(id).unwrap())
+            .collect()
+    }
+
+    /// Returns roughly how much work is due but not yet taken, counting work
+    /// cancelled or rescheduled since it became due.
+    fn backlog(&mut self) -> usize {
+        self.promote();
+        self.ready.iter().map(|x| x.len()).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.works.is_empty()
+    }
+
+    fn soonest(&mut self) -> time::Duration {
+        self.promote();
+        if self.ready.iter().any(|x| !x.is_empty()) {
+            return time::Duration::ZERO;
+        }
+        match self.heap.peek() {
+            Some(x) => self.works[&x.id].until(),
+            None => time::Duration::MAX,
+        }
+    }
+
+    fn work_available(&mut self) -> bool {
+        self.soonest() == time::Duration::ZERO
+    }
+}
+
+/// Lets the pool grow beyond its size while there is more due work than idle
+/// threads.
+#[derive(Clone, Copy, Debug)]
+pub struct Elastic {
+    pub max_threads: usize,
+    /// How long a thread beyond the size of the pool waits for work before it
+    /// exits.
+    pub keep_alive: time::Duration,
+}
+
+struct SharedWorkerContext {
+    work_list: WorkList,
+    stop: bool,
+    /// Set along with `stop` for threads to exit without running the work
+    /// left.
+    abort: bool,
+    /// Number of threads the pool is sized to.
+    target: usize,
+    /// Number of threads running, and how many of them are waiting for work.
+    live: usize,
+    idle: usize,
+    elastic: Option<Elastic>,
+}
+
+/// Called on the worker thread with the label and payload of a job which
+/// panicked.
+pub type PanicHook = dyn Fn(Option<&str>, &(dyn Any + Send)) + Send + Sync;
+
+struct WorkerContext {
+    cvar: Condvar,
+    shared: Mutex<SharedWorkerContext>,
+    /// Notified when a thread exits.
+    exited: Condvar,
+    handles: Mutex<Vec<thread::JoinHandle<()>>>,
+    panic_hook: Mutex<Option<Arc<PanicHook>>>,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+}
+
+/// Counts of the jobs of a `Workers`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// Threads running, and how many of them are waiting for work.
+    pub threads: usize,
+    pub idle: usize,
+    /// Jobs waiting to run, whether due or not.
+    pub pending: usize,
+    /// Jobs which have finished running, including those which panicked.
+    pub completed: u64,
+    pub panicked: u64,
+}
+
+/// How to run a posted job.
+#[derive(Clone, Debug, Default)]
+pub struct JobOptions {
+    /// How long to wait before running the job.
+    pub timeout: time::Duration,
+    pub priority: Priority,
+    /// Name for the job passed to the panic hook.
+    pub label: Option<String>,
+}
+
+pub struct Workers {
+    ctx: Arc<WorkerContext>,
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (other.when, other.id).cmp(&(self.when, self.id))
+    }
+}
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        (self.when, self.id) == (other.when, other.id)
+    }
+}
+impl Eq for Entry {}
+
+/// Why a job didn't produce a result.
+#[derive(Debug)]
+pub enum JobError {
+    /// The job panicked with the given message.
+    Panicked(String),
+    /// The job was dropped without being run.
+    Cancelled,
+    /// The result was already taken from the handle.
+    Taken,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::Panicked(message) => write!(f, "job panicked: {}", message),
+            JobError::Cancelled => write!(f, "job was cancelled"),
+            JobError::Taken => write!(f, "job result was already taken"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+enum Slot<T> {
+    Pending,
+    Done(Result<T, JobError>),
+    Taken,
+}
+
+struct JobState<T> {
+    slot: Mutex<Slot<T>>,
+    cvar: Condvar,
+}
+
+/// Handle to the result of a posted job, which can also cancel or
+/// reschedule the job until it starts.
+pub struct JobHandle<T> {
+    state: Arc<JobState<T>>,
+    id: u64,
+    ctx: Weak<WorkerContext>,
+}
+
+/// The job's end of a `JobHandle`. Completes the handle with
+/// `JobError::Cancelled` if the job is dropped without having run.
+struct Completer<T> {
+    state: Arc<JobState<T>>,
+}
+
+impl<T> JobState<T> {
+    fn new() -> (Arc<JobState<T>>, Completer<T>) {
+        let state = Arc::new(JobState {
+            slot: Mutex::new(Slot::Pending),
+            cvar: Condvar::new(),
+        });
+        let completer = Completer {
+            state: state.clone(),
+        };
+        (state, completer)
+    }
+}
+
+impl<T> JobHandle<T> {
+    /// Removes the job from the schedule if it hasn't started yet, completing
+    /// the handle with `JobError::Cancelled`. Returns whether it was removed.
+    pub fn cancel(&self) -> bool {
+        let Some(ctx) = self.ctx.upgrade() else {
+            return false;
+        };
+        let work = ctx.shared.lock().unwrap().work_list.cancel(self.id);
+        // Dropping the work completes the handle, so do it after unlocking.
+        work.is_some()
+    }
+
+    /// Moves the job's deadline to `timeout` from now if it hasn't started
+    /// yet, such as to push an idle timeout further out. Returns whether the
+    /// job was rescheduled.
+    pub fn reschedule(&self, timeout: time::Duration) -> bool {
+        let Some(ctx) = self.ctx.upgrade() else {
+            return false;
+        };
+        let mut shared = ctx.shared.lock().unwrap();
+        let rescheduled = shared
+            .work_list
+            .reschedule(self.id, Instant::now().add(timeout));
+        // An earlier deadline has to wake a thread waiting for a later one.
+        ctx.cvar.notify_all();
+        rescheduled
+    }
+
+    /// Takes the result out of the slot if the job has finished.
+    fn take(slot: &mut Slot<T>) -> Option<Result<T, JobError>> {
+        match mem::replace(slot, Slot::Taken) {
+            Slot::Done(result) => Some(result),
+            Slot::Pending => {
+                *slot = Slot::Pending;
+                None
+            }
+            Slot::Taken => Some(Err(JobError::Taken)),
+        }
+    }
+
+    /// Blocks until the job has finished and returns its result, or why there
+    /// is none. Returns `JobError::Taken` if the result was already taken
+    /// with `try_get` or `join_timeout`.
+    pub fn join(self) -> Result<T, JobError> {
+        let slot = self.state.slot.lock().unwrap();
+        let mut slot = self
+            .state
+            .cvar
+            .wait_while(slot, |x| matches!(x, Slot::Pending))
+            .unwrap();
+        JobHandle::take(&mut slot).unwrap()
+    }
+
+    /// Returns the result if the job has finished, without blocking. The
+    /// result can only be taken once, after which `JobError::Taken` is
+    /// returned instead.
+    pub fn try_get(&mut self) -> Option<Result<T, JobError>> {
+        JobHandle::take(&mut self.state.slot.lock().unwrap())
+    }
+
+    /// Like `try_get`, but waits up to `timeout` for the job to finish.
+    pub fn join_timeout(&mut self, timeout: time::Duration) -> Option<Result<T, JobError>> {
+        let slot = self.state.slot.lock().unwrap();
+        let (mut slot, _) = self
+            .state
+            .cvar
+            .wait_timeout_while(slot, timeout, |x| matches!(x, Slot::Pending))
+            .unwrap();
+        JobHandle::take(&mut slot)
+    }
+}
+
+impl<T> Completer<T> {
+    fn complete(&self, result: Result<T, JobError>) {
+        *self.state.slot.lock().unwrap() = Slot::Done(result);
+        self.state.cvar.notify_all();
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let pending = matches!(*self.state.slot.lock().unwrap(), Slot::Pending);
+        if pending {
+            self.complete(Err(JobError::Cancelled));
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// When a recurring job runs again.
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    /// Every period after the previous run was due, so that the runs don't
+    /// drift. Runs which fall behind are run as soon as a thread is free.
+    FixedRate(time::Duration),
+    /// The period after the previous run finished.
+    FixedDelay(time::Duration),
+    /// Whenever the cron expression matches the wall-clock time.
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// Returns when to run after a run which was due at `due`, or `None` if
+    /// never again.
+    fn next(&self, due: Instant) -> Option<Instant> {
+        match self {
+            Schedule::FixedRate(period) => Some(due.add(*period)),
+            Schedule::FixedDelay(period) => Some(Instant::now().add(*period)),
+            Schedule::Cron(cron) => {
+                let now = SystemTime::now();
+                // Not within the minute the previous run was due, in case the
+                // wall clock is behind the monotonic one.
+                let after = now.max(now.add(due.saturating_duration_since(Instant::now())));
+                let next = cron.next_after(after)?;
+                Some(Instant::now().add(next.duration_since(now).unwrap_or_default()))
+            }
+        }
+    }
+}
+
+struct RecurrenceState {
+    /// Id of the next run in the work list. Only changed with the work list
+    /// locked.
+    pending: Mutex<Option<u64>>,
+    stopped: AtomicBool,
+    runs: AtomicU64,
+}
+
+/// Handle to a recurring job, which can stop it from running again.
+pub struct RecurringHandle {
+    state: Arc<RecurrenceState>,
+    ctx: Weak<WorkerContext>,
+}
+
+impl RecurringHandle {
+    /// Stops the job from running again, removing its next run from the
+    /// schedule. A run already in progress still finishes. Returns whether
+    /// the job was still recurring.
+    pub fn stop(&self) -> bool {
+        let recurring = !self.state.stopped.swap(true, Ordering::SeqCst);
+        if let Some(ctx) = self.ctx.upgrade() {
+            let mut shared = ctx.shared.lock().unwrap();
+            let pending = self.state.pending.lock().unwrap().take();
+            let work = pending.and_then(|id| shared.work_list.cancel(id));
+            drop(shared);
+            drop(work);
+        }
+        recurring
+    }
+
+    /// Returns whether the job won't run again, because it was stopped,
+    /// reached its maximum number of runs or panicked.
+    pub fn is_stopped(&self) -> bool {
+        self.state.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of runs which have finished.
+    pub fn runs(&self) -> u64 {
+        self.state.runs.load(Ordering::SeqCst)
+    }
+}
+
+/// A recurring job between runs, which puts itself back in the work list.
+struct Recurrence {
+    func: Box<dyn FnMut() + Send>,
+    schedule: Schedule,
+    max_runs: Option<u64>,
+    priority: Priority,
+    label: Option<String>,
+    state: Arc<RecurrenceState>,
+    ctx: Weak<WorkerContext>,
+}
+
+impl Recurrence {
+    /// Adds the next run at `when`, or stops if there is none or the workers
+    /// are stopping.
+    fn schedule(self, when: Option<Instant>) {
+        let state = self.state.clone();
+        let (Some(ctx), Some(when)) = (self.ctx.upgrade(), when) else {
+            state.stopped.store(true, Ordering::SeqCst);
+            return;
+        };
+        let mut shared = ctx.shared.lock().unwrap();
+        if shared.stop || state.stopped.load(Ordering::SeqCst) {
+            state.stopped.store(true, Ordering::SeqCst);
+            return;
+        }
+        let (priority, label) = (self.priority, self.label.clone());
+        let work = Work {
+            func: Box::new(move || self.run(when)),
+            when,
+            priority,
+            label,
+        };
+        *state.pending.lock().unwrap() = Some(shared.work_list.push(work));
+        ctx.cvar.notify_one();
+        Workers::grow(&ctx, &mut shared);
+    }
+
+    fn run(mut self, due: Instant) {
+        if self.state.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| (self.func)())) {
+            self.state.stopped.store(true, Ordering::SeqCst);
+            panic::resume_unwind(payload);
+        }
+        let runs = self.state.runs.fetch_add(1, Ordering::SeqCst) + 1;
+        let next = match self.max_runs {
+            Some(max) if runs >= max => None,
+            _ => self.schedule.next(due),
+        };
+        self.schedule(next)
+    }
+}
+
+impl SharedWorkerContext {
+    fn exiting(&self) -> bool {
+        self.stop && (self.abort || self.work_list.is_empty())
+    }
+}
+
+/// How `Workers::shutdown` treats the jobs which haven't run yet.
+#[derive(Clone, Copy, Debug)]
+pub enum Shutdown {
+    /// Run every job, waiting for those which aren't due yet.
+    Drain,
+    /// Run the jobs which are already due and discard the rest.
+    DueOnly,
+    /// Run no more jobs, only finishing those already running.
+    Abort,
+    /// Drain for at most the given time, and then abort.
+    Deadline(time::Duration),
+}
+
+/// A job taken out of the schedule by a shutdown before it ran. Dropping it
+/// completes its handle with `JobError::Cancelled`.
+pub struct UnrunJob {
+    work: Work,
+}
+
+impl UnrunJob {
+    pub fn label(&self) -> Option<&str> {
+        self.work.label.as_deref()
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.work.priority
+    }
+
+    /// Returns when the job was due to run.
+    pub fn due(&self) -> Instant {
+        self.work.when
+    }
+
+    /// Runs the job on this thread. A panic is returned through the job's
+    /// handle and then carries on unwinding.
+    pub fn run(self) {
+        (self.work.func)()
+    }
+}
+
+/// What a shutdown left undone.
+pub struct ShutdownReport {
+    /// Jobs which never ran, in the order they were due.
+    pub unrun: Vec<UnrunJob>,
+    /// Number of jobs still running when the shutdown returned. Their threads
+    /// exit once they finish, and can be waited for with `join`.
+    pub running: usize,
+}
+
+impl Workers {
+    pub fn new(threads: usize) -> Workers {
+        let ctx = WorkerContext {
+            cvar: Condvar::new(),
+            shared: Mutex::new(SharedWorkerContext {
+                work_list: WorkList::new(),
+                stop: false,
+                abort: false,
+                target: threads,
+                live: 0,
+                idle: 0,
+                elastic: None,
+            }),
+            exited: Condvar::new(),
+            handles: Mutex::new(Vec::new()),
+            panic_hook: Mutex::new(None),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+        };
+        let arc_ctx = Arc::new(ctx);
+        Workers { ctx: arc_ctx }
+    }
+
+    pub fn start(&mut self) {
+        let mut shared = self.ctx.shared.lock().unwrap();
+        if shared.live >= shared.target {
+            panic!("no threads to create")
+        }
+        while shared.live < shared.target {
+            Workers::spawn(&self.ctx, &mut shared);
+        }
+    }
+
+    pub fn join(&mut self) {
+        self.stop();
+        loop {
+            let handles = mem::take(&mut *self.ctx.handles.lock().unwrap());
+            if handles.is_empty() {
+                return;
+            }
+            handles.into_iter().for_each(|x| x.join().unwrap());
+        }
+    }
+
+    /// Changes the number of threads to `threads`, starting any new ones
+    /// straight away. Surplus threads exit once they have finished their job,
+    /// or in elastic mode once they have been idle for the keep-alive.
+    pub fn resize(&self, threads: usize) {
+        let mut shared = self.ctx.shared.lock().unwrap();
+        shared.target = threads;
+        while shared.live < shared.target {
+            Workers::spawn(&self.ctx, &mut shared);
+        }
+        self.ctx.cvar.notify_all();
+    }
+
+    /// Sets whether to start extra threads when due work backs up, and how
+    /// many.
+    pub fn set_elastic(&self, elastic: Option<Elastic>) {
+        let mut shared = self.ctx.shared.lock().unwrap();
+        shared.elastic = elastic;
+        Workers::grow(&self.ctx, &mut shared);
+        self.ctx.cvar.notify_all();
+    }
+
+    fn spawn(ctx: &Arc<WorkerContext>, shared: &mut SharedWorkerContext) {
+        shared.live += 1;
+        let thread_ctx = ctx.clone();
+        let handle = thread::spawn(move || Workers::thread_work(thread_ctx));
+        let mut handles = ctx.handles.lock().unwrap();
+        handles.retain(|x| !x.is_finished());
+        handles.push(handle);
+    }
+
+    /// In elastic mode, starts another thread if there is more due work than
+    /// idle threads to take it.
+    fn grow(ctx: &Arc<WorkerContext>, shared: &mut SharedWorkerContext) {
+        let Some(elastic) = shared.elastic else {
+            return;
+        };
+        if !shared.stop
+            && shared.live < elastic.max_threads
+            && shared.work_list.backlog() > shared.idle
+        {
+            Workers::spawn(ctx, shared);
+        }
+    }
+
+    pub fn stop(&self) {
+        let mut shared = self.ctx.shared.lock().unwrap();
+        shared.stop = true;
+        self.ctx.cvar.notify_all();
+    }
+
+    /// Stops the threads, running the jobs left as given by `mode`, and
+    /// returns the jobs which weren't run. Waits for the threads to exit,
+    /// unless the deadline of `Shutdown::Deadline` passes first.
+    pub fn shutdown(&mut self, mode: Shutdown) -> ShutdownReport {
+        let mut shared = self.ctx.shared.lock().unwrap();
+        shared.stop = true;
+        let mut unrun = match mode {
+            Shutdown::DueOnly => shared.work_list.take(Some(Instant::now())),
+            Shutdown::Abort => {
+                shared.abort = true;
+                shared.work_list.take(None)
+            }
+            Shutdown::Drain | Shutdown::Deadline(_) => Vec::new(),
+        };
+        self.ctx.cvar.notify_all();
+
+        let deadline = match mode {
+            Shutdown::Deadline(timeout) => Instant::now().checked_add(timeout),
+            _ => None,
+        };
+        while shared.live > 0 {
+            let Some(deadline) = deadline else {
+                shared = self.ctx.exited.wait(shared).unwrap();
+                continue;
+            };
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            shared = self.ctx.exited.wait_timeout(shared, left).unwrap().0;
+        }
+        // Past the deadline, or without threads to run it, whatever is left
+        // is aborted.
+        shared.abort = true;
+        unrun.extend(shared.work_list.take(None));
+        unrun.sort_by_key(|x| x.when);
+        let running = shared.live - shared.idle;
+        self.ctx.cvar.notify_all();
+        drop(shared);
+
+        if running == 0 {
+            self.join();
+        }
+        ShutdownReport {
+            unrun: unrun.into_iter().map(|work| UnrunJob { work }).collect(),
+            running,
+        }
+    }
+
+    /// Runs `f` on a worker thread as soon as one is free, returning a handle
+    /// to its result.
+    pub fn post<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.post_with(f, &JobOptions::default())
+    }
+
+    /// Runs `f` on a worker thread once `timeout` has passed, returning a
+    /// handle to its result. A panic in `f` is caught and returned through
+    /// the handle.
+    pub fn post_timeout<F, T>(&self, f: F, timeout: time::Duration) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.post_with(
+            f,
+            &JobOptions {
+                timeout,
+                ..JobOptions::default()
+            },
+        )
+    }
+
+    /// Like `post_timeout`, but once due `f` runs before any due jobs of a
+    /// lower priority, unless they have been waiting for long.
+    pub fn post_priority<F, T>(
+        &self,
+        f: F,
+        timeout: time::Duration,
+        priority: Priority,
+    ) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.post_with(
+            f,
+            &JobOptions {
+                timeout,
+                priority,
+                ..JobOptions::default()
+            },
+        )
+    }
+
+    /// Runs `f` on a worker thread as given by `options`, returning a handle
+    /// to its result. A panic in `f` is returned through the handle and passed
+    /// to the panic hook. Once the workers are stopping, `f` is dropped
+    /// instead and the handle completes with `JobError::Cancelled`.
+    pub fn post_with<F, T>(&self, f: F, options: &JobOptions) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (state, completer) = JobState::new();
+        let work = Work {
+            func: Box::new(move || match panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(result) => completer.complete(Ok(result)),
+                Err(payload) => {
+                    completer.complete(Err(JobError::Panicked(panic_message(&*payload))));
+                    panic::resume_unwind(payload);
+                }
+            }),
+            when: Instant::now().add(options.timeout),
+            priority: options.priority,
+            label: options.label.clone(),
+        };
+
+        let mut shared = self.ctx.shared.lock().unwrap();
+        if shared.stop {
+            drop(shared);
+            drop(work);
+            // Without the context there is nothing to cancel or reschedule.
+            return JobHandle {
+                state,
+                id: 0,
+                ctx: Weak::new(),
+            };
+        }
+        let id = shared.work_list.push(work);
+        self.ctx.cvar.notify_one();
+        Workers::grow(&self.ctx, &mut shared);
+        JobHandle {
+            state,
+            id,
+            ctx: Arc::downgrade(&self.ctx),
+        }
+    }
+
+    /// Runs `f` on a worker thread repeatedly as given by `schedule`, at most
+    /// `max_runs` times if given, returning a handle to stop it with. Every
+    /// run has the priority and label in `options`, and `options.timeout`
+    /// delays the start of the schedule. A panic in `f` stops the job and is
+    /// passed to the panic hook. The job isn't scheduled again once the
+    /// workers are stopping.
+    pub fn post_recurring<F>(
+        &self,
+        f: F,
+        schedule: Schedule,
+        max_runs: Option<u64>,
+        options: &JobOptions,
+    ) -> RecurringHandle
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let state = Arc::new(RecurrenceState {
+            pending: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+            runs: AtomicU64::new(0),
+        });
+        let first = schedule
+            .next(Instant::now().add(options.timeout))
+            .filter(|_| max_runs != Some(0));
+        let recurrence = Recurrence {
+            func: Box::new(f),
+            schedule,
+            max_runs,
+            priority: options.priority,
+            label: options.label.clone(),
+            state: state.clone(),
+            ctx: Arc::downgrade(&self.ctx),
+        };
+        recurrence.schedule(first);
+        RecurringHandle {
+            state,
+            ctx: Arc::downgrade(&self.ctx),
+        }
+    }
+
+    /// Sets the function called with the label and payload of every job which
+    /// panics, after the panic has been caught.
+    pub fn set_panic_hook<F>(&self, hook: F)
+    where
+        F: Fn(Option<&str>, &(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        *self.ctx.panic_hook.lock().unwrap() = Some(Arc::new(hook));
+    }
+
+    pub fn stats(&self) -> Stats {
+        let shared = self.ctx.shared.lock().unwrap();
+        Stats {
+            threads: shared.live,
+            idle: shared.idle,
+            pending: shared.work_list.len(),
+            completed: self.ctx.completed.load(Ordering::SeqCst),
+            panicked: self.ctx.panicked.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Runs a job, catching a panic so that the thread keeps serving.
+    fn run_work(ctx: &WorkerContext, work: Work) {
+        let Work { func, label, .. } = work;
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(func)) {
+            ctx.panicked.fetch_add(1, Ordering::SeqCst);
+            let hook = ctx.panic_hook.lock().unwrap().clone();
+            if let Some(hook) = hook {
+                // A panicking hook mustn't take the thread down either.
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(label.as_deref(), &*payload)));
+            }
+        }
+        ctx.completed.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn thread_work(ctx: Arc<WorkerContext>) {
+        let mut shared = ctx.shared.lock().unwrap();
+        loop {
+            let idle_since = Instant::now();
+            shared.idle += 1;
+            while !shared.exiting() && !shared.work_list.work_available() {
+                let mut duration = shared.work_list.soonest();
+                if shared.live > shared.target {
+                    let keep_alive = shared
+                        .elastic
+                        .map_or(time::Duration::ZERO, |x| x.keep_alive);
+                    let left = keep_alive.saturating_sub(idle_since.elapsed());
+                    if left.is_zero() {
+                        shared.idle -= 1;
+                        shared.live -= 1;
+                        ctx.exited.notify_all();
+                        return;
+                    }
+                    duration = duration.min(left);
+                }
+                shared = ctx.cvar.wait_timeout(shared, duration).unwrap().0
+            }
+            shared.idle -= 1;
+
+            if shared.exiting() {
+                shared.live -= 1;
+                ctx.exited.notify_all();
+                return;
+            }
+
+            let work = shared.work_list.pop().unwrap();
+            Workers::grow(&ctx, &mut shared);
+            drop(shared);
+
+            Workers::run_work(&ctx, work);
+            shared = ctx.shared.lock().unwrap();
+            // Shrinking the pool takes effect as soon as surplus threads have
+            // finished their job, even while there is more work to take.
+            if shared.live > shared.target && shared.elastic.is_none() {
+                shared.live -= 1;
+                ctx.exited.notify_all();
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn result_is_taken_once() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let mut handle = workers.post(|| 7);
+        let result = loop {
+            if let Some(result) = handle.try_get() {
+                break result;
+            }
+            thread::yield_now();
+        };
+        assert_eq!(result.unwrap(), 7);
+        assert!(matches!(handle.try_get(), Some(Err(JobError::Taken))));
+        let later = handle.join_timeout(time::Duration::ZERO);
+        assert!(matches!(later, Some(Err(JobError::Taken))));
+        assert!(matches!(handle.join(), Err(JobError::Taken)));
+        workers.join();
+    }
+
+    #[test]
+    fn fixed_rate_schedule_doesnt_drift() {
+        let due = Instant::now();
+        let period = time::Duration::from_millis(20);
+        let next = Schedule::FixedRate(period).next(due).unwrap();
+        assert_eq!(next, due.add(period));
+    }
+
+    #[test]
+    fn fixed_delay_schedule_waits_from_now() {
+        let due = Instant::now().sub(time::Duration::from_secs(1));
+        let period = time::Duration::from_millis(20);
+        let before = Instant::now();
+        let next = Schedule::FixedDelay(period).next(due).unwrap();
+        assert!(next >= before.add(period));
+    }
+
+    /// Runs a job recording its start times with `schedule` until it stops.
+    fn recurring_runs(schedule: Schedule, max_runs: u64, duration: time::Duration) -> Vec<Instant> {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let starts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = starts.clone();
+        let job = move || {
+            recorded.lock().unwrap().push(Instant::now());
+            thread::sleep(duration);
+        };
+        let handle = workers.post_recurring(job, schedule, Some(max_runs), &JobOptions::default());
+        while !handle.is_stopped() {
+            thread::sleep(time::Duration::from_millis(5));
+        }
+        assert_eq!(handle.runs(), max_runs);
+        assert!(!handle.stop());
+        workers.join();
+        let starts = starts.lock().unwrap().clone();
+        starts
+    }
+
+    #[test]
+    fn fixed_rate_stops_after_max_runs() {
+        let period = time::Duration::from_millis(30);
+        let first = Instant::now();
+        let starts = recurring_runs(
+            Schedule::FixedRate(period),
+            4,
+            time::Duration::from_millis(10),
+        );
+        assert_eq!(starts.len(), 4);
+        for (k, start) in starts.iter().enumerate() {
+            assert!(*start >= first.add(period * (k as u32 + 1)));
+        }
+        // Runs are due a period after the previous one was due, not after it
+        // finished, so they don't fall behind by the time the job takes.
+        assert!(starts[3] < first.add(period * 4 + time::Duration::from_millis(25)));
+    }
+
+    #[test]
+    fn fixed_delay_waits_after_each_run() {
+        let period = time::Duration::from_millis(20);
+        let took = time::Duration::from_millis(30);
+        let starts = recurring_runs(Schedule::FixedDelay(period), 3, took);
+        assert_eq!(starts.len(), 3);
+        for pair in starts.windows(2) {
+            assert!(pair[1] - pair[0] >= period + took);
+        }
+    }
+
+    #[test]
+    fn recurrence_stops_when_told() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let period = time::Duration::from_millis(10);
+        let handle = workers.post_recurring(
+            || {},
+            Schedule::FixedRate(period),
+            None,
+            &JobOptions::default(),
+        );
+        thread::sleep(time::Duration::from_millis(55));
+        assert!(handle.stop());
+        let runs = handle.runs();
+        assert!(runs >= 2);
+        thread::sleep(time::Duration::from_millis(40));
+        assert_eq!(handle.runs(), runs);
+        assert!(handle.is_stopped());
+        workers.join();
+    }
+
+    #[test]
+    fn recurring_panics_reach_the_hook_labelled() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let labels = Arc::new(Mutex::new(Vec::new()));
+        let seen = labels.clone();
+        workers.set_panic_hook(move |label, _| {
+            seen.lock().unwrap().push(label.map(str::to_string));
+        });
+        let options = JobOptions {
+            priority: Priority::High,
+            label: Some("ticker".to_string()),
+            ..JobOptions::default()
+        };
+        let period = time::Duration::from_millis(5);
+        let handle = workers.post_recurring(
+            || panic!("tick"),
+            Schedule::FixedDelay(period),
+            None,
+            &options,
+        );
+        while !handle.is_stopped() {
+            thread::sleep(period);
+        }
+        workers.join();
+        assert_eq!(*labels.lock().unwrap(), [Some("ticker".to_string())]);
+        assert_eq!(workers.stats().panicked, 1);
+    }
+
+    #[test]
+    fn shrinking_takes_effect_with_work_queued() {
+        let mut workers = Workers::new(4);
+        workers.start();
+        let job = time::Duration::from_millis(2);
+        let handles: Vec<_> = (0..500)
+            .map(|_| workers.post(move || thread::sleep(job)))
+            .collect();
+        workers.resize(1);
+        thread::sleep(time::Duration::from_millis(50));
+        let stats = workers.stats();
+        assert_eq!(stats.threads, 1);
+        assert!(stats.pending > 0);
+
+        workers.resize(3);
+        assert_eq!(workers.stats().threads, 3);
+        handles.into_iter().for_each(|x| x.join().unwrap());
+        workers.join();
+        assert_eq!(workers.stats().threads, 0);
+    }
+
+    #[test]
+    fn elastic_threads_grow_and_retire() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let keep_alive = time::Duration::from_millis(100);
+        workers.set_elastic(Some(Elastic {
+            max_threads: 4,
+            keep_alive,
+        }));
+        let job = time::Duration::from_millis(50);
+        let handles: Vec<_> = (0..8)
+            .map(|_| workers.post(move || thread::sleep(job)))
+            .collect();
+        assert_eq!(workers.stats().threads, 4);
+        handles.into_iter().for_each(|x| x.join().unwrap());
+
+        thread::sleep(keep_alive / 2);
+        assert_eq!(workers.stats().threads, 4);
+        thread::sleep(keep_alive * 2);
+        let stats = workers.stats();
+        assert_eq!((stats.threads, stats.idle), (1, 1));
+        workers.join();
+    }
+
+    #[test]
+    fn posts_after_shutdown_are_cancelled() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let report = workers.shutdown(Shutdown::Drain);
+        assert!(report.unrun.is_empty());
+
+        let handle = workers.post(|| 1);
+        assert!(!handle.reschedule(time::Duration::ZERO));
+        assert!(matches!(handle.join(), Err(JobError::Cancelled)));
+        let options = JobOptions::default();
+        let period = time::Duration::from_millis(1);
+        let recurring = workers.post_recurring(|| {}, Schedule::FixedRate(period), None, &options);
+        assert!(recurring.is_stopped());
+        assert_eq!(workers.stats().pending, 0);
+    }
+
+    fn work(when: Instant, priority: Priority, label: &str) -> Work {
+        Work {
+            func: Box::new(|| {}),
+            when,
+            priority,
+            label: Some(label.to_string()),
+        }
+    }
+
+    #[test]
+    fn stale_entries_dont_pile_up() {
+        let mut list = WorkList::new();
+        let later = Instant::now().add(time::Duration::from_secs(3600));
+        let id = list.push(work(later, Priority::Normal, "debounced"));
+        for ms in 1..=1000 {
+            assert!(list.reschedule(id, later.add(time::Duration::from_millis(ms))));
+            assert!(list.heap.len() <= 2);
+        }
+        let ids: Vec<u64> = (0..1000)
+            .map(|_| list.push(work(later, Priority::Normal, "cancelled")))
+            .collect();
+        for id in ids {
+            assert!(list.cancel(id).is_some());
+        }
+        assert!(list.heap.len() <= 2);
+        assert_eq!(list.len(), 1);
+        assert!(list.cancel(id).is_some());
+        assert!(!list.reschedule(id, later));
+    }
+
+    #[test]
+    fn cancel_before_due() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = ran.clone();
+        let job = move || flag.store(true, Ordering::SeqCst);
+        let handle = workers.post_timeout(job, time::Duration::from_millis(50));
+        assert!(handle.cancel());
+        assert!(!handle.cancel());
+        assert!(!handle.reschedule(time::Duration::ZERO));
+        assert_eq!(workers.stats().pending, 0);
+        assert!(matches!(handle.join(), Err(JobError::Cancelled)));
+        thread::sleep(time::Duration::from_millis(100));
+        assert!(!ran.load(Ordering::SeqCst));
+        workers.join();
+    }
+
+    #[test]
+    fn reschedule_later_and_earlier() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let later = time::Duration::from_millis(100);
+        let handle = workers.post_timeout(Instant::now, time::Duration::from_millis(10));
+        let rescheduled = Instant::now();
+        assert!(handle.reschedule(later));
+        assert!(handle.join().unwrap() >= rescheduled.add(later));
+
+        // The thread is waiting for the first deadline and has to be woken
+        // for the earlier one.
+        let mut handle = workers.post_timeout(Instant::now, time::Duration::from_secs(3600));
+        thread::sleep(time::Duration::from_millis(10));
+        assert!(handle.reschedule(time::Duration::ZERO));
+        let result = handle.join_timeout(time::Duration::from_secs(5));
+        assert!(matches!(result, Some(Ok(_))));
+        workers.join();
+    }
+
+    #[test]
+    fn cancel_when_due_but_not_taken() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let (started, running) = std::sync::mpsc::channel();
+        let blocker = workers.post(move || {
+            started.send(()).unwrap();
+            blocked.recv().unwrap();
+        });
+        running.recv().unwrap();
+        let handle = workers.post(|| 1);
+        thread::sleep(time::Duration::from_millis(10));
+        assert_eq!(workers.stats().pending, 1);
+        assert!(handle.cancel());
+        assert_eq!(workers.stats().pending, 0);
+        release.send(()).unwrap();
+        blocker.join().unwrap();
+        assert!(matches!(handle.join(), Err(JobError::Cancelled)));
+        workers.join();
+        assert_eq!(workers.stats().completed, 1);
+    }
+
+    #[test]
+    fn cancel_after_running() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let mut handle = workers.post(|| 1);
+        let result = handle.join_timeout(time::Duration::from_secs(5));
+        assert!(matches!(result, Some(Ok(1))));
+        assert!(!handle.cancel());
+        assert!(!handle.reschedule(time::Duration::ZERO));
+        workers.join();
+    }
+
+    /// Pops every due job off `list`, returning their labels in order.
+    fn pop_labels(list: &mut WorkList) -> Vec<String> {
+        std::iter::from_fn(|| list.pop())
+            .map(|x| x.label.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn due_jobs_run_by_priority() {
+        let mut list = WorkList::new();
+        let due = Instant::now();
+        list.push(work(due, Priority::Low, "low"));
+        list.push(work(due, Priority::Normal, "normal"));
+        list.push(work(due, Priority::High, "high"));
+        let later = due.add(time::Duration::from_secs(3600));
+        list.push(work(later, Priority::High, "not due"));
+        assert_eq!(pop_labels(&mut list), ["high", "normal", "low"]);
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn equal_priorities_run_in_order() {
+        let mut list = WorkList::new();
+        let due = Instant::now();
+        let earlier = due.sub(time::Duration::from_millis(100));
+        for label in ["a", "b", "c"] {
+            list.push(work(due, Priority::Normal, label));
+        }
+        list.push(work(earlier, Priority::Normal, "due first"));
+        list.push(work(due, Priority::Normal, "d"));
+        assert_eq!(pop_labels(&mut list), ["due first", "a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn waiting_low_jobs_overtake_later_high_ones() {
+        let mut list = WorkList::new();
+        let now = Instant::now();
+        let ago = |ms| now.sub(time::Duration::from_millis(ms));
+        // Due 1.5s before the high jobs, so raised by two classes more.
+        list.push(work(ago(2200), Priority::Low, "low"));
+        list.push(work(ago(700), Priority::High, "high 1"));
+        list.push(work(ago(700), Priority::High, "high 2"));
+        list.push(work(ago(2200), Priority::Normal, "normal"));
+        assert_eq!(pop_labels(&mut list), ["normal", "low", "high 1", "high 2"]);
+
+        // Due in the same burst, the low job waits for every high one.
+        list.push(work(ago(300), Priority::Low, "low"));
+        list.push(work(ago(200), Priority::High, "high 1"));
+        list.push(work(ago(100), Priority::High, "high 2"));
+        assert_eq!(pop_labels(&mut list), ["high 1", "high 2", "low"]);
+    }
+
+    fn labelled(label: &str, timeout: time::Duration) -> JobOptions {
+        JobOptions {
+            timeout,
+            label: Some(label.to_string()),
+            ..JobOptions::default()
+        }
+    }
+
+    fn unrun_labels(report: &ShutdownReport) -> Vec<&str> {
+        report.unrun.iter().map(|x| x.label().unwrap()).collect()
+    }
+
+    #[test]
+    fn due_only_shutdown_returns_future_jobs() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let ran = Arc::new(AtomicU64::new(0));
+        let now = time::Duration::ZERO;
+        let due: Vec<_> = (0..3)
+            .map(|k| {
+                let ran = ran.clone();
+                let job = move || ran.fetch_add(1, Ordering::SeqCst);
+                workers.post_with(job, &labelled(&format!("due {}", k), now))
+            })
+            .collect();
+        let hour = time::Duration::from_secs(3600);
+        let later = workers.post_with(|| 0, &labelled("later", hour * 2));
+        let soon = workers.post_with(|| 0, &labelled("soon", hour));
+
+        let report = workers.shutdown(Shutdown::DueOnly);
+        assert_eq!(unrun_labels(&report), ["soon", "later"]);
+        assert_eq!(report.running, 0);
+        assert_eq!(ran.load(Ordering::SeqCst), 3);
+        due.into_iter().for_each(|x| assert!(x.join().is_ok()));
+        drop(report);
+        assert!(matches!(soon.join(), Err(JobError::Cancelled)));
+        assert!(matches!(later.join(), Err(JobError::Cancelled)));
+    }
+
+    #[test]
+    fn abort_shutdown_lets_the_running_job_finish() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let (started, running) = std::sync::mpsc::channel();
+        let took = time::Duration::from_millis(50);
+        let first = workers.post(move || {
+            started.send(()).unwrap();
+            thread::sleep(took);
+            Instant::now()
+        });
+        running.recv().unwrap();
+        let now = time::Duration::ZERO;
+        workers.post_with(|| {}, &labelled("queued 1", now));
+        workers.post_with(|| {}, &labelled("queued 2", now));
+
+        let report = workers.shutdown(Shutdown::Abort);
+        let returned = Instant::now();
+        assert_eq!(unrun_labels(&report), ["queued 1", "queued 2"]);
+        assert_eq!(report.running, 0);
+        assert!(first.join().unwrap() <= returned);
+        assert_eq!(workers.stats().completed, 1);
+    }
+
+    #[test]
+    fn deadline_shutdown_reports_the_running_job() {
+        let mut workers = Workers::new(1);
+        workers.start();
+        let (started, running) = std::sync::mpsc::channel();
+        let took = time::Duration::from_millis(300);
+        let first = workers.post(move || {
+            started.send(()).unwrap();
+            thread::sleep(took);
+        });
+        running.recv().unwrap();
+        let queued = workers.post_with(|| {}, &labelled("queued", time::Duration::ZERO));
+
+        let deadline = time::Duration::from_millis(50);
+        let before = Instant::now();
+        let report = workers.shutdown(Shutdown::Deadline(deadline));
+        let waited = before.elapsed();
+        assert!(waited >= deadline && waited < took, "{:?}", waited);
+        assert_eq!(unrun_labels(&report), ["queued"]);
+        assert_eq!(report.running, 1);
+        drop(report);
+        assert!(matches!(queued.join(), Err(JobError::Cancelled)));
+        workers.join();
+        assert!(first.join().is_ok());
+        assert_eq!(workers.stats().threads, 0);
+    }
+}